@@ -0,0 +1,89 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use gumball::core::cpu::Cpu;
+use gumball::core::emulator::Emulator;
+use gumball::core::frontend::NullRenderer;
+use gumball::core::mmu::Mmu;
+use gumball::core::ppu::PPU;
+use gumball::core::registers::{LCDC, LY, STAT};
+
+// One frame of the Game Boy's 4.19 MHz clock
+const CYCLES_PER_FRAME: u64 = 70224;
+
+// A loop of common loads, ALU ops and a jump:
+//     LD HL, 0xC000
+// loop:
+//     LD A, 0x12; LD B, A; ADD A, B; INC C; XOR D
+//     LD (HL), A; LD A, (HL); CP 0x10
+//     JR loop
+const PROGRAM: [u8; 15] = [
+    0x21, 0x00, 0xC0, 0x3E, 0x12, 0x47, 0x80, 0x0C, 0xAA, 0x77, 0x7E, 0xFE, 0x10, 0x18, 0xF4,
+];
+
+fn program_rom() -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x100 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    rom
+}
+
+// Background tiles and a full line of sprites, so every fetch has work to do
+fn busy_mmu() -> Mmu {
+    let mut mmu = Mmu::init();
+    mmu.initialize_from_bytes(&program_rom()).unwrap();
+    mmu.set(LCDC as u16, 0x00);
+    for address in 0x8000..0x8800u16 {
+        mmu.set(address, address as u8);
+    }
+    for tile in 0..0x400u16 {
+        mmu.set(0x9800 + tile, (tile % 128) as u8);
+    }
+    for i in 0..10u16 {
+        mmu.set(0xFE00 + i * 4, 16);
+        mmu.set(0xFE01 + i * 4, 8 + i as u8 * 16);
+        mmu.set(0xFE02 + i * 4, i as u8);
+    }
+    mmu.set(LCDC as u16, 0x93);
+    mmu
+}
+
+// Emulated clock rate: the throughput is in cycles, so Melem/s reads as MHz
+fn bench_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("emulator");
+    group.throughput(Throughput::Elements(CYCLES_PER_FRAME));
+    group.bench_function("frame", |b| {
+        let mut emulator = Emulator::headless(busy_mmu()).unwrap();
+        b.iter(|| emulator.run_frames(1).unwrap());
+    });
+    group.finish();
+}
+
+fn bench_cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(1000));
+    group.bench_function("execute", |b| {
+        let mut mem = Mmu::init_with_vec(PROGRAM.to_vec());
+        let mut cpu = Cpu::default();
+        b.iter(|| {
+            for _ in 0..1000 {
+                black_box(cpu.execute(&mut mem));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn bench_draw_line(c: &mut Criterion) {
+    let mut mem = busy_mmu();
+    mem.set(LY as u16, 0);
+    let mut ppu = PPU::new();
+    // Run into mode 3 of the first line so its sprites have been found
+    while mem.get(STAT) & 0b11 != 3 {
+        ppu.render(&mut mem, 4, &mut NullRenderer).unwrap();
+    }
+    c.bench_function("ppu/draw_line", |b| {
+        b.iter(|| ppu.draw_line(black_box(&mem)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_frames, bench_cpu, bench_draw_line);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+#![no_main]
+
+use gumball::core::cpu::{Cpu, Reg};
+use gumball::core::mmu::Mmu;
+use libfuzzer_sys::fuzz_target;
+
+// Instructions to run per input
+const MAX_STEPS: usize = 1000;
+// AF, BC, DE, HL and SP, little-endian, ahead of the program
+const HEADER_LEN: usize = 10;
+// Opcodes with no instruction. `execute` panics on these by design, so a run
+// stops when it reaches one.
+const ILLEGAL_OPCODES: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+// The input is the starting registers followed by a program loaded at 0x0100
+fuzz_target!(|data: &[u8]| {
+    if data.len() < HEADER_LEN {
+        return;
+    }
+    let (header, program) = data.split_at(HEADER_LEN);
+    let program = &program[..program.len().min(0x8000 - 0x100)];
+    let mut mem = Mmu::init_with_vec(program.to_vec());
+    let mut cpu = Cpu::default();
+    for (reg, bytes) in [Reg::AF, Reg::BC, Reg::DE, Reg::HL, Reg::SP]
+        .into_iter()
+        .zip(header.chunks(2))
+    {
+        cpu.set_register(reg, u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    for _ in 0..MAX_STEPS {
+        cpu.handle_interrupts(&mut mem);
+        cpu.enable_ime_delayed();
+        if cpu.halted || cpu.stopped {
+            return;
+        }
+        if ILLEGAL_OPCODES.contains(&mem.get(cpu.pc() as usize)) {
+            return;
+        }
+        cpu.execute(&mut mem);
+    }
+});
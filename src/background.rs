@@ -1,6 +1,16 @@
-use crate::mmu::Mmu;
-use crate::registers::LCDC;
-use crate::WindowCreator;
+use gumball::core::frontend::DebugView;
+use gumball::core::mmu::Mmu;
+use gumball::core::ppu::{palette_color, Colors};
+use gumball::core::registers::{BGP, LCDC};
+
+use crate::media::WindowCreator;
+
+// Decode the 2-bit color index of pixel `x` (0 is leftmost) from a tile row
+pub fn color_index(byte1: u8, byte2: u8, x: usize) -> u8 {
+    let bit1 = (byte1 >> (7 - x)) & 1;
+    let bit2 = (byte2 >> (7 - x)) & 1;
+    (bit1 << 1) | bit2
+}
 
 pub struct BackgroundDisplay {
     window_creator: WindowCreator,
@@ -19,13 +29,13 @@ impl BackgroundDisplay {
         };
         let mut tiles = Vec::new();
         for i in 0..1024 {
-            let tile = mem.get(base + i);
+            let tile = mem.peek(base + i);
             tiles.push(tile);
         }
         tiles
     }
 
-    pub fn draw_tiles(&mut self, mem: &Mmu) -> Result<(), String> {
+    pub fn draw_tiles(&mut self, mem: &Mmu, colors: &Colors) -> Result<(), String> {
         let tiles = self.get_tiles(mem);
 
         let mut texture = self
@@ -35,6 +45,7 @@ impl BackgroundDisplay {
             .map_err(|e| e.to_string())?;
 
         let pitch = 256 * 3;
+        let bgp = mem.get(BGP);
 
         texture
             .with_lock(None, |buffer: &mut [u8], _| {
@@ -44,23 +55,15 @@ impl BackgroundDisplay {
                     for j in 0..8 {
                         let b12 = !((mem.get(LCDC) & 0x10) != 0 || (tile & 0x80) != 0);
                         let tile_addr = 0x8000 | ((b12 as u16) << 12) | ((tile as u16) << 4);
-                        let byte1 = mem.get(tile_addr as usize + j * 2);
-                        let byte2 = mem.get(tile_addr as usize + j * 2 + 1);
+                        let byte1 = mem.peek(tile_addr as usize + j * 2);
+                        let byte2 = mem.peek(tile_addr as usize + j * 2 + 1);
                         for k in 0..8 {
-                            let bit1 = (byte1 >> (7 - k)) & 1;
-                            let bit2 = (byte2 >> (7 - k)) & 1;
-                            let color_index = (bit1 << 1) | bit2;
-                            let color = match color_index {
-                                0 => [255, 255, 255],
-                                1 => [192, 192, 192],
-                                2 => [96, 96, 96],
-                                3 => [0, 0, 0],
-                                _ => unreachable!(),
-                            };
+                            let (r, g, b) =
+                                palette_color(colors, bgp, color_index(byte1, byte2, k));
                             let offset = (y + j) * pitch + (x + k) * 3;
-                            buffer[offset] = color[0];
-                            buffer[offset + 1] = color[1];
-                            buffer[offset + 2] = color[2];
+                            buffer[offset] = r;
+                            buffer[offset + 1] = g;
+                            buffer[offset + 2] = b;
                         }
                     }
                 }
@@ -72,3 +75,9 @@ impl BackgroundDisplay {
         Ok(())
     }
 }
+
+impl DebugView for BackgroundDisplay {
+    fn draw(&mut self, mem: &Mmu, colors: &Colors) -> Result<(), String> {
+        self.draw_tiles(mem, colors)
+    }
+}
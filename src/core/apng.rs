@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 144;
+// Keep one frame in this many, which is plenty to show a glitch and halves
+// the size
+const DECIMATION: u64 = 2;
+
+// Collects frames for an animated PNG of the screen. The frame count goes in
+// the file's header, so nothing is written until `finish`.
+pub struct ApngRecorder {
+    path: PathBuf,
+    // Each distinct frame and how many 60ths of a second it's shown for
+    frames: Vec<(Vec<u8>, u16)>,
+    // Frames to drop before the next one is kept
+    frames_to_drop: u64,
+}
+
+impl ApngRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ApngRecorder {
+            path: path.into(),
+            frames: Vec::new(),
+            frames_to_drop: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Add the 160x144 RGB24 `pixels` of a finished frame. A frame that's the
+    // same as the last one just shows that one for longer.
+    pub fn add_frame(&mut self, pixels: &[u8]) {
+        if self.frames_to_drop > 0 {
+            self.frames_to_drop -= 1;
+            return;
+        }
+        self.frames_to_drop = DECIMATION - 1;
+        let pixels = &pixels[..(WIDTH * HEIGHT * 3) as usize];
+        match self.frames.last_mut() {
+            Some((last, delay)) if last == pixels && *delay < u16::MAX - DECIMATION as u16 => {
+                *delay += DECIMATION as u16;
+            }
+            _ => self.frames.push((pixels.to_vec(), DECIMATION as u16)),
+        }
+    }
+
+    // Write the file. Does nothing if no frames were recorded.
+    pub fn finish(self) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+        let file = File::create(&self.path).map_err(|e| e.to_string())?;
+        self.encode(BufWriter::new(file))
+            .map_err(|e| format!("Error writing {}: {e}", self.path.display()))
+    }
+
+    fn encode<W: Write>(&self, out: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(out, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        for (pixels, delay) in &self.frames {
+            writer.set_frame_delay(*delay, 60)?;
+            writer.write_image_data(pixels)?;
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_frame(shade: u8) -> Vec<u8> {
+        vec![shade; (WIDTH * HEIGHT * 3) as usize]
+    }
+
+    #[test]
+    fn test_repeated_frames_are_merged() {
+        let mut recorder = ApngRecorder::new("unused.png");
+        for shade in [0, 0, 0, 0, 0xFF, 0xFF] {
+            recorder.add_frame(&solid_frame(shade));
+        }
+        let delays: Vec<u16> = recorder.frames.iter().map(|(_, delay)| *delay).collect();
+        assert_eq!(delays, [4, 2]);
+
+        let mut bytes = Vec::new();
+        recorder.encode(&mut bytes).unwrap();
+        let reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, 2);
+    }
+}
@@ -0,0 +1,814 @@
+use std::cmp::min;
+
+use crate::core::frontend::AudioSink;
+use crate::core::mmu::Mmu;
+use crate::core::registers::*;
+
+// The cycles passed to `update` are T-cycles of the 4.19 MHz clock
+const CPU_CLOCK_SPEED: f64 = 4_194_304.0;
+// Weight of each new sample in the one-pole low-pass on the mix. Takes the
+// edge off the square waves without dulling them much.
+const LOW_PASS_ALPHA: f32 = 0.5;
+// How much of the DMG output capacitor's charge survives each T-cycle. Raised
+// to the cycles per sample, it sets how fast the high-pass removes DC offset.
+const HIGH_PASS_CHARGE_FACTOR: f64 = 0.999958;
+const FADE_DURATION: f32 = 0.0;
+// Samples of each channel kept for the oscilloscope, about 12 ms at 44.1 kHz
+pub const HISTORY_LEN: usize = 512;
+// The loudest a channel's sample gets, either way from zero
+pub const MAX_AMPLITUDE: f32 = 15.0;
+
+pub struct APU {
+    // Cycles left over since the last sample. Kept fractional so the average
+    // rate is exact rather than truncated to a whole number of cycles.
+    clock_cycles: f64,
+    // Last output of the low-pass filter on each side
+    low_pass: [f32; 2],
+    // One high-pass per channel, removing the DC offset before mixing
+    high_pass: [HighPass; 3],
+    div_apu: u32,
+    last_div: u8,
+    // Samples mixed during the current update, handed to the sink at the end
+    samples: Vec<[f32; 2]>,
+    sample_rate: i32,
+    pulse_channel_1: PulseChannel,
+    pulse_channel_2: PulseChannel,
+    wave_channel: WaveChannel,
+    // Bit n - 1 is set if channel n is audible, independent of NR52
+    channel_mask: u8,
+    // Only kept while something is showing it
+    history: Option<Box<ChannelHistory>>,
+}
+
+// The last HISTORY_LEN samples of each channel as generated, before muting,
+// filtering and mixing
+pub struct ChannelHistory {
+    samples: [[f32; HISTORY_LEN]; 4],
+    // Where the next sample goes, which is also the oldest one
+    next: usize,
+}
+
+impl ChannelHistory {
+    fn new() -> Self {
+        ChannelHistory {
+            samples: [[0.0; HISTORY_LEN]; 4],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, samples: [f32; 4]) {
+        for (channel, sample) in self.samples.iter_mut().zip(samples) {
+            channel[self.next] = sample;
+        }
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    // The samples of `channel` (1-4), oldest first
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        let samples = &self.samples[channel - 1];
+        samples[self.next..]
+            .iter()
+            .chain(&samples[..self.next])
+            .copied()
+    }
+}
+
+impl APU {
+    pub fn new(sample_rate: i32) -> Self {
+        APU {
+            clock_cycles: 0.0,
+            low_pass: [0.0; 2],
+            high_pass: Default::default(),
+            div_apu: 0,
+            last_div: 0,
+            samples: Vec::new(),
+            sample_rate,
+            pulse_channel_1: PulseChannel::new(
+                1,
+                sample_rate,
+                Some(0xFF10),
+                0xFF11,
+                0xFF12,
+                0xFF13,
+                0xFF14,
+            ),
+            pulse_channel_2: PulseChannel::new(
+                2,
+                sample_rate,
+                None,
+                0xFF16,
+                0xFF17,
+                0xFF18,
+                0xFF19,
+            ),
+            wave_channel: WaveChannel::new(sample_rate),
+            channel_mask: 0b1111,
+            history: None,
+        }
+    }
+
+    // Start keeping each channel's recent samples, for `history`
+    pub fn enable_history(&mut self) {
+        self.history
+            .get_or_insert_with(|| Box::new(ChannelHistory::new()));
+    }
+
+    pub fn history(&self) -> Option<&ChannelHistory> {
+        self.history.as_deref()
+    }
+
+    // Change the rate samples are generated at, keeping the channels' state.
+    // A lower rate than the sink plays at raises the pitch.
+    pub fn set_sample_rate(&mut self, sample_rate: i32) {
+        self.sample_rate = sample_rate;
+        self.pulse_channel_1.channel.sample_rate = sample_rate as f32;
+        self.pulse_channel_2.channel.sample_rate = sample_rate as f32;
+        self.wave_channel.sample_rate = sample_rate;
+    }
+
+    // Mute or unmute a channel (1-4) for debugging
+    pub fn set_channel_enabled(&mut self, channel: usize, enabled: bool) {
+        assert!((1..=4).contains(&channel), "Invalid channel {channel}");
+        if enabled {
+            self.channel_mask |= 1 << (channel - 1);
+        } else {
+            self.channel_mask &= !(1 << (channel - 1));
+        }
+    }
+
+    pub fn channel_enabled(&self, channel: usize) -> bool {
+        self.channel_mask & (1 << (channel - 1)) != 0
+    }
+
+    pub fn toggle_channel(&mut self, channel: usize) {
+        self.set_channel_enabled(channel, !self.channel_enabled(channel));
+    }
+
+    // Mute every channel except `channel`
+    pub fn solo(&mut self, channel: usize) {
+        for i in 1..=4 {
+            self.set_channel_enabled(i, i == channel);
+        }
+    }
+
+    fn mute(&self, channel: usize, sample: f32) -> f32 {
+        if self.channel_enabled(channel) {
+            sample
+        } else {
+            0.0
+        }
+    }
+
+    // Advance by `cycles` and queue the new samples on `sink`. With no sink
+    // the channels still run so the status bits stay right, but nothing is
+    // mixed.
+    pub fn update(&mut self, cycles: u32, mmu: &mut Mmu, sink: Option<&mut dyn AudioSink>) {
+        let cycles_per_sample = CPU_CLOCK_SPEED / self.sample_rate as f64;
+        let charge_factor = HIGH_PASS_CHARGE_FACTOR.powf(cycles_per_sample) as f32;
+        self.clock_cycles += cycles as f64;
+        while self.clock_cycles >= cycles_per_sample {
+            self.clock_cycles -= cycles_per_sample;
+            let sample1 = self.pulse_channel_1.generate_sample(mmu);
+            let sample2 = self.pulse_channel_2.generate_sample(mmu);
+            let sample3 = self.wave_channel.generate_sample(mmu);
+            if let Some(history) = &mut self.history {
+                // There's no noise channel yet, so channel 4 stays flat
+                history.push([sample1, sample2, sample3, 0.0]);
+            }
+            let sample1 = self.mute(1, sample1);
+            let sample2 = self.mute(2, sample2);
+            let sample3 = self.mute(3, sample3);
+            let sample1 = self.high_pass[0].apply(sample1, charge_factor);
+            let sample2 = self.high_pass[1].apply(sample2, charge_factor);
+            let sample3 = self.high_pass[2].apply(sample3, charge_factor);
+            if sink.is_none() {
+                continue;
+            }
+            let mix = mix(
+                [sample1, sample2, sample3, 0.0],
+                mmu.get(NR50),
+                mmu.get(NR51),
+                mmu.get(NR52),
+            );
+            for (low_pass, side) in self.low_pass.iter_mut().zip(mix) {
+                *low_pass += LOW_PASS_ALPHA * (side - *low_pass);
+            }
+            self.samples.push(self.low_pass);
+        }
+        if self.samples.is_empty() {
+            return;
+        }
+        if let Some(sink) = sink {
+            sink.queue_samples(&self.samples);
+        }
+        self.samples.clear();
+    }
+
+    pub fn inc_div_apu(&mut self, mmu: &Mmu) {
+        if self.last_div & 0x10 == 0x10 && mmu.get(0xFF04) & 0x10 == 0 {
+            self.div_apu = self.div_apu.wrapping_add(1);
+            self.pulse_channel_1.div_apu = self.pulse_channel_1.div_apu.wrapping_add(1);
+            self.pulse_channel_2.div_apu = self.pulse_channel_2.div_apu.wrapping_add(1);
+        }
+        self.last_div = mmu.get(0xFF04);
+    }
+}
+
+// Mix the four channels into `[left, right]` the way the DMG does. NR51
+// routes each channel to either side or both (bits 4-7 left, 0-3 right),
+// each side's sum is scaled by its NR50 volume (1-8 eighths), and NR52's
+// power bit silences everything. The sum is divided by four whatever is
+// playing, so four channels at full volume reach exactly 1.0 and one
+// channel is as loud alone as it is alongside the others.
+fn mix(samples: [f32; 4], nr50: u8, nr51: u8, nr52: u8) -> [f32; 2] {
+    if nr52 & 0x80 == 0 {
+        return [0.0; 2];
+    }
+    let side = |routing: u8, volume: u8| {
+        let sum: f32 = (0..4)
+            .filter(|channel| routing & (1 << channel) != 0)
+            .map(|channel| samples[channel])
+            .sum();
+        sum / (4.0 * MAX_AMPLITUDE) * ((volume & 0b111) + 1) as f32 / 8.0
+    };
+    [side(nr51 >> 4, nr50 >> 4), side(nr51, nr50)]
+}
+
+// The capacitor on the output: it charges toward the input, and the output is
+// whatever it hasn't caught up with yet, so a constant input decays to zero
+#[derive(Debug, Default)]
+struct HighPass {
+    capacitor: f32,
+}
+
+impl HighPass {
+    fn apply(&mut self, input: f32, charge_factor: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * charge_factor;
+        output
+    }
+}
+
+#[derive(Debug, Default)]
+struct SquareWaveChannel {
+    frequency: f32,   // Frequency of the square wave in Hz
+    duty_cycle: f32,  // Duty cycle (fraction of the period the wave is high)
+    sample_rate: f32, // Sample rate of the audio
+    phase: f32,       // Current phase of the wave
+    amplitude: u8,    // Amplitude of the wave
+    fade_out_samples: u32,
+    fade_in_samples: u32,
+}
+
+impl SquareWaveChannel {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            frequency: 0.0,
+            duty_cycle: 0.5,
+            sample_rate,
+            phase: 0.0,
+            amplitude: 0,
+            fade_out_samples: 0,
+            fade_in_samples: 0,
+        }
+    }
+
+    fn generate_sample(&mut self) -> f32 {
+        // Calculate the period of the wave
+        let period = self.sample_rate / self.frequency;
+
+        // Calculate the current sample value based on the phase and duty cycle
+        let sample = if self.phase < self.duty_cycle * period {
+            self.amplitude as f32
+        } else {
+            -1.0 * self.amplitude as f32
+        };
+
+        // if self.fade_out_samples > 0 {
+        //     sample *= self.fade_out_samples as f32 / (FADE_DURATION * self.sample_rate);
+        //     self.fade_out_samples -= 1;
+        // }
+
+        // if self.fade_in_samples < (FADE_DURATION * self.sample_rate) as u32 {
+        //     let factor = self.fade_in_samples as f32 / (FADE_DURATION * self.sample_rate);
+        //     sample *= factor * factor;
+        //     println!("Sample: {}", sample as u8);
+        //     self.fade_in_samples += 1;
+        // }
+
+        // Update the phase, wrapping around if necessary
+        self.phase = (self.phase + 1.0) % period;
+
+        sample
+    }
+
+    fn fade_in(&mut self) {
+        self.fade_in_samples = 0;
+        self.fade_out_samples = 0;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PulseChannel {
+    enabled: bool,
+    channel_number: usize,
+    triggered: bool,
+    nrx0: Option<u16>,
+    nrx1: u16,
+    nrx2: u16,
+    nrx3: u16,
+    nrx4: u16,
+    sample_rate: i32,
+    div_apu: u32,
+    prev_div_apu_vol: u32,
+    prev_div_apu_freq: u32,
+    duty_cycle: u8,
+    length_timer: u8,
+    length_timer_enabled: bool,
+    period_value: u16,
+    initial_volume: u8,
+    volume_envelope_increasing: bool,
+    volume_sweep_pace: u8,
+    freq_sweep_period: u8,
+    freq_sweep_increase: bool,
+    freq_sweep_shift: u8,
+    freq_sweep_enabled: bool,
+    // Set once a sweep calculation has run in subtraction mode since trigger
+    freq_sweep_negate_used: bool,
+    shadow_period: u16,
+    cycles: u32,
+    channel: SquareWaveChannel,
+    accumulated_cycles: u32,
+}
+
+impl PulseChannel {
+    pub fn new(
+        channel: usize,
+        sample_rate: i32,
+        nrx0: Option<u16>,
+        nrx1: u16,
+        nrx2: u16,
+        nrx3: u16,
+        nrx4: u16,
+    ) -> Self {
+        Self {
+            enabled: false,
+            channel_number: channel,
+            triggered: false,
+            nrx0,
+            nrx1,
+            nrx2,
+            nrx3,
+            nrx4,
+            sample_rate,
+            div_apu: 0,
+            prev_div_apu_vol: 0,
+            prev_div_apu_freq: 0,
+            duty_cycle: 0,
+            length_timer: 0,
+            length_timer_enabled: false,
+            period_value: 0,
+            initial_volume: 0,
+            volume_envelope_increasing: false,
+            volume_sweep_pace: 0,
+            freq_sweep_period: 0,
+            freq_sweep_increase: false,
+            freq_sweep_shift: 0,
+            freq_sweep_enabled: false,
+            freq_sweep_negate_used: false,
+            shadow_period: 0,
+            cycles: 0,
+            channel: SquareWaveChannel::new(sample_rate as f32),
+            accumulated_cycles: 0,
+        }
+    }
+
+    fn enable(&mut self, mmu: &mut Mmu) {
+        self.enabled = true;
+        mmu[NR52] |= 1 << (self.channel_number - 1);
+    }
+
+    fn disable(&mut self, mmu: &mut Mmu) {
+        self.enabled = false;
+        mmu[NR52] &= !(1 << (self.channel_number - 1));
+    }
+
+    fn read_period(&self, mmu: &Mmu) -> u16 {
+        let nr13 = mmu[self.nrx3 as usize];
+        let nr14 = mmu[self.nrx4 as usize];
+        ((nr14 & 0b0000_0111) as u16) << 8 | nr13 as u16
+    }
+
+    fn read_sweep(&mut self, nr10: u8) {
+        self.freq_sweep_increase = (nr10 & 0b0000_1000) == 0;
+        self.freq_sweep_shift = nr10 & 0b0000_0111;
+    }
+
+    // The next period from the frequency sweep. This can exceed 0x7FF, which
+    // the callers treat as an overflow that disables the channel.
+    fn sweep_calculation(&mut self) -> u16 {
+        let delta = self.shadow_period >> self.freq_sweep_shift;
+        if self.freq_sweep_increase {
+            self.shadow_period + delta
+        } else {
+            self.freq_sweep_negate_used = true;
+            self.shadow_period - delta
+        }
+    }
+
+    fn trigger_sweep(&mut self, mmu: &mut Mmu, nr10: u8) {
+        self.read_sweep(nr10);
+        self.shadow_period = self.read_period(mmu);
+        self.freq_sweep_period = (nr10 & 0b0111_0000) >> 4;
+        self.freq_sweep_enabled = self.freq_sweep_period != 0 || self.freq_sweep_shift != 0;
+        self.freq_sweep_negate_used = false;
+        self.prev_div_apu_freq = self.div_apu;
+        // With a nonzero shift the overflow check runs immediately on trigger
+        if self.freq_sweep_shift != 0 && self.sweep_calculation() > 0x7FF {
+            self.disable(mmu);
+        }
+    }
+
+    fn update_period(&mut self, mmu: &mut Mmu) {
+        if let Some(nr10) = self.nrx0.map(|x| mmu[x as usize]) {
+            self.read_sweep(nr10);
+            // Switching from subtraction to addition after a calculation
+            // used subtraction disables the channel
+            if self.freq_sweep_negate_used && self.freq_sweep_increase {
+                self.disable(mmu);
+            }
+            if self.freq_sweep_enabled
+                && self.freq_sweep_period != 0
+                && (self.div_apu >> 2).wrapping_sub(self.prev_div_apu_freq >> 2)
+                    >= self.freq_sweep_period as u32
+            {
+                self.prev_div_apu_freq = self.div_apu;
+                self.freq_sweep_period = (nr10 & 0b0111_0000) >> 4;
+                let new_period_value = self.sweep_calculation();
+                if new_period_value > 0x7FF {
+                    self.disable(mmu);
+                } else if self.freq_sweep_shift != 0 {
+                    self.shadow_period = new_period_value;
+                    let nr14 = mmu[self.nrx4 as usize];
+                    mmu.set(self.nrx3, (new_period_value & 0xFF) as u8);
+                    mmu.set(
+                        self.nrx4,
+                        (nr14 & 0b1111_1000) | (0x7 & (new_period_value >> 8)) as u8,
+                    );
+                    // The new period is run through the calculation again
+                    // for a second overflow check, but not written back
+                    if self.sweep_calculation() > 0x7FF {
+                        self.disable(mmu);
+                    }
+                }
+            }
+        }
+        self.period_value = self.read_period(mmu);
+        self.channel.frequency = 131072.0 / (2048.0 - self.period_value as f32);
+    }
+
+    fn update_volume(&mut self, mmu: &mut Mmu) {
+        let nr12 = mmu[self.nrx2 as usize];
+        if nr12 & 0b1111_1000 == 0 {
+            self.disable(mmu);
+            return;
+        }
+        self.initial_volume = (nr12 & 0b1111_0000) >> 4;
+        if self.triggered {
+            self.channel.amplitude = self.initial_volume;
+            self.volume_envelope_increasing = (nr12 & 0b0000_1000) != 0;
+            self.volume_sweep_pace = nr12 & 0b0000_0111;
+        } else if self.volume_sweep_pace != 0 {
+            if (self.div_apu >> 3) - (self.prev_div_apu_vol >> 3) >= self.volume_sweep_pace as u32 {
+                if self.volume_envelope_increasing {
+                    self.channel.amplitude = min(15, self.channel.amplitude.saturating_add(1));
+                } else {
+                    if self.channel_number == 2 {
+                        println!("Amplitude: {}", self.channel.amplitude);
+                    }
+                    self.channel.amplitude = self.channel.amplitude.saturating_sub(1);
+                }
+                self.prev_div_apu_vol = self.div_apu;
+            }
+        } else {
+            self.channel.amplitude = self.initial_volume;
+        }
+    }
+
+    fn update_duty_cycle(&mut self, mmu: &Mmu) {
+        let nr11 = mmu[self.nrx1 as usize];
+        self.duty_cycle = (nr11 & 0b1100_0000) >> 6;
+        match self.duty_cycle {
+            0 => self.channel.duty_cycle = 0.125,
+            1 => self.channel.duty_cycle = 0.25,
+            2 => self.channel.duty_cycle = 0.5,
+            3 => self.channel.duty_cycle = 0.75,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn generate_sample(&mut self, mmu: &mut Mmu) -> f32 {
+        let nr10 = self.nrx0.map(|x| mmu[x as usize]);
+        let nr14 = mmu[self.nrx4 as usize];
+        if nr14 & 0b1000_0000 != 0 {
+            // Acknowledge the trigger so it only fires once per write
+            mmu.set(self.nrx4, nr14 & 0b0111_1111);
+            self.triggered = true;
+            self.enable(mmu);
+            if let Some(nr10) = nr10 {
+                self.trigger_sweep(mmu, nr10);
+            }
+        }
+        self.update_period(mmu);
+        self.update_volume(mmu);
+        self.update_duty_cycle(mmu);
+        self.triggered = false;
+        if self.enabled {
+            self.channel.generate_sample()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WaveChannel {
+    pub enabled: bool,
+    triggered: bool,
+    sample_rate: i32,
+    period_value: u16,
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+}
+
+impl WaveChannel {
+    pub fn new(sample_rate: i32) -> Self {
+        Self {
+            enabled: false,
+            triggered: false,
+            sample_rate,
+            period_value: 0,
+            frequency: 0.0,
+            volume: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    fn enable(&mut self, mmu: &mut Mmu) {
+        self.enabled = true;
+        mmu[NR52] |= 0b0000_0100;
+    }
+
+    fn disable(&mut self, mmu: &mut Mmu) {
+        self.enabled = false;
+        mmu[NR52] &= 0b1111_1011;
+    }
+
+    fn generate_sample(&mut self, mmu: &mut Mmu) -> f32 {
+        let nr30 = mmu[0xFF1A];
+        let nr31 = mmu[0xFF1B];
+        let nr32 = mmu[0xFF1C];
+        let nr33 = mmu[0xFF1D];
+        let nr34 = mmu[0xFF1E];
+        if nr34 & 0b1000_0000 != 0 {
+            self.triggered = true;
+            self.enable(mmu);
+        }
+        if nr30 & 0b1000_0000 == 0 {
+            self.disable(mmu);
+            return 0.0;
+        }
+        let wave_ram = mmu.get_wave_ram();
+        let period_value = ((nr34 & 0b0000_0111) as u16) << 8 | nr33 as u16;
+        let frequency = 65536.0 / (2048.0 - period_value as f32);
+
+        let sample_index = (self.phase as usize) % 32;
+        let sample = if sample_index % 2 == 0 {
+            wave_ram[sample_index / 2] & 0xF
+        } else {
+            wave_ram[sample_index / 2] >> 4
+        };
+
+        self.phase = (self.phase + frequency / self.sample_rate as f32) % 32.0;
+
+        match wave_volume_shift(nr32) {
+            Some(shift) => (sample >> shift) as f32,
+            None => 0.0,
+        }
+    }
+}
+
+// NR32 bits 5-6 select mute, 100%, 50% or 25% as a right shift of the 4-bit
+// wave sample
+fn wave_volume_shift(nr32: u8) -> Option<u8> {
+    match (nr32 >> 5) & 0b11 {
+        0b00 => None,
+        0b01 => Some(0),
+        0b10 => Some(1),
+        _ => Some(2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wave_volume_levels() {
+        let mut mmu = Mmu::init();
+        let mut channel = WaveChannel::new(44100);
+        for address in WAVE_RAM_START..WAVE_RAM_START + 0x10 {
+            mmu.set(address as u16, 0xFF);
+        }
+        mmu.set(0xFF1A, 0x80);
+        mmu.set(0xFF1E, 0x87);
+
+        let mut level = |nr32| {
+            mmu.set(0xFF1C, nr32);
+            channel.generate_sample(&mut mmu)
+        };
+        assert_eq!(level(0b0000_0000), 0.0);
+        assert_eq!(level(0b0010_0000), 15.0);
+        assert_eq!(level(0b0100_0000), 7.0);
+        assert_eq!(level(0b0110_0000), 3.0);
+    }
+
+    struct CountingSink(usize);
+
+    impl AudioSink for CountingSink {
+        fn sample_rate(&self) -> i32 {
+            44100
+        }
+
+        fn queue_samples(&mut self, samples: &[[f32; 2]]) {
+            self.0 += samples.len();
+        }
+    }
+
+    #[test]
+    fn test_sample_count_matches_rate() {
+        const CYCLES_PER_FRAME: u32 = 70224;
+        const FRAMES: u32 = 60;
+        let mut mmu = Mmu::init();
+        // Channel 1 playing a 1 kHz square wave
+        mmu.set(0xFF11, 0b1000_0000);
+        mmu.set(0xFF12, 0xF0);
+        mmu.set(0xFF13, 0x83);
+        mmu.set(0xFF14, 0b1000_0111);
+        let mut apu = APU::new(44100);
+        let mut sink = CountingSink(0);
+        for frame in 1..=FRAMES {
+            for _ in 0..CYCLES_PER_FRAME / 4 {
+                apu.update(4, &mut mmu, Some(&mut sink));
+            }
+            let expected = 44100.0 * (frame * CYCLES_PER_FRAME) as f64 / CPU_CLOCK_SPEED;
+            assert!((sink.0 as f64 - expected).abs() <= 1.0, "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn test_mix_doesnt_attenuate_a_lone_channel() {
+        let full = MAX_AMPLITUDE;
+        let alone = mix([full, 0.0, 0.0, 0.0], 0x77, 0xFF, 0x80);
+        let together = mix([full; 4], 0x77, 0xFF, 0x80);
+        assert_eq!(alone, [0.25, 0.25]);
+        assert_eq!(together, [1.0, 1.0]);
+        // Muting the others leaves channel 1 as loud as it was
+        let others = mix([0.0, full, full, full], 0x77, 0xFF, 0x80);
+        assert_eq!(together[0] - others[0], alone[0]);
+    }
+
+    #[test]
+    fn test_mix_follows_nr50_nr51_and_nr52() {
+        let samples = [MAX_AMPLITUDE, 0.0, -MAX_AMPLITUDE / 2.0, 0.0];
+        // Channel 1 left only, channel 3 right only
+        assert_eq!(mix(samples, 0x77, 0x14, 0x80), [0.25, -0.125]);
+        // Left at volume 4/8, right at 1/8
+        assert_eq!(mix(samples, 0x30, 0xFF, 0x80), [0.0625, 0.015625]);
+        assert_eq!(mix(samples, 0x77, 0xFF, 0x00), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc_offset() {
+        let charge_factor = HIGH_PASS_CHARGE_FACTOR.powf(CPU_CLOCK_SPEED / 44100.0) as f32;
+        let mut filter = HighPass::default();
+        let first = filter.apply(1.0, charge_factor);
+        assert_eq!(first, 1.0);
+        let mut last = first;
+        for _ in 0..44100 {
+            let output = filter.apply(1.0, charge_factor);
+            assert!(output <= last);
+            last = output;
+        }
+        assert!(last.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_nr52_reads_channel_status() {
+        let mut mmu = Mmu::init();
+        let mut apu = APU::new(44100);
+        // The boot ROM leaves channel 1 on
+        assert_eq!(mmu.get(NR52), 0xF1);
+
+        // Trigger channel 2, and channel 3 with its DAC on
+        mmu.set(0xFF17, 0xF0);
+        mmu.set(0xFF19, 0x80);
+        mmu.set(0xFF1A, 0x80);
+        mmu.set(0xFF1E, 0x80);
+        apu.update(100, &mut mmu, None);
+        assert_eq!(mmu.get(NR52), 0xF7);
+
+        // The CPU can't write the channel bits
+        mmu.set(NR52 as u16, 0x80);
+        assert_eq!(mmu.get(NR52), 0xF7);
+
+        // Turning channel 3's DAC off stops it
+        mmu.set(0xFF1A, 0x00);
+        apu.update(100, &mut mmu, None);
+        assert_eq!(mmu.get(NR52), 0xF3);
+    }
+
+    #[test]
+    fn test_history_keeps_latest_samples_per_channel() {
+        let mut mmu = Mmu::init();
+        let mut apu = APU::new(44100);
+        apu.update(1000, &mut mmu, None);
+        assert!(apu.history().is_none());
+
+        // Channel 2 at full volume with a 75% duty cycle
+        mmu.set(0xFF16, 0b1100_0000);
+        mmu.set(0xFF17, 0xF0);
+        mmu.set(0xFF18, 0x00);
+        mmu.set(0xFF19, 0b1000_0111);
+        apu.enable_history();
+        apu.update(
+            CPU_CLOCK_SPEED as u32 / 44100 * 2 * HISTORY_LEN as u32,
+            &mut mmu,
+            None,
+        );
+        let history = apu.history().unwrap();
+        let channel_2: Vec<f32> = history.channel(2).collect();
+        assert_eq!(channel_2.len(), HISTORY_LEN);
+        assert!(channel_2.contains(&MAX_AMPLITUDE));
+        assert!(channel_2.contains(&-MAX_AMPLITUDE));
+        assert!(history.channel(4).all(|sample| sample == 0.0));
+
+        // Once it wraps, the oldest sample comes first
+        let mut history = ChannelHistory::new();
+        for i in 0..=HISTORY_LEN {
+            history.push([i as f32; 4]);
+        }
+        let channel_1: Vec<f32> = history.channel(1).collect();
+        assert_eq!(channel_1[0], 1.0);
+        assert_eq!(channel_1[HISTORY_LEN - 1], HISTORY_LEN as f32);
+    }
+
+    fn channel_1() -> PulseChannel {
+        PulseChannel::new(1, 44100, Some(0xFF10), 0xFF11, 0xFF12, 0xFF13, 0xFF14)
+    }
+
+    #[test]
+    fn test_sweep_overflow_on_trigger() {
+        let mut mmu = Mmu::init();
+        let mut channel = channel_1();
+        mmu.set(0xFF10, 0b0000_0001);
+        mmu.set(0xFF12, 0xF0);
+        mmu.set(0xFF13, 0xF0);
+        mmu.set(0xFF14, 0b1000_0111);
+
+        assert_eq!(channel.generate_sample(&mut mmu), 0.0);
+        assert!(!channel.enabled);
+        assert_eq!(mmu.get(NR52) & 0b1, 0);
+    }
+
+    #[test]
+    fn test_sweep_no_overflow_with_zero_shift() {
+        let mut mmu = Mmu::init();
+        let mut channel = channel_1();
+        mmu.set(0xFF10, 0b0111_0000);
+        mmu.set(0xFF12, 0xF0);
+        mmu.set(0xFF13, 0xF0);
+        mmu.set(0xFF14, 0b1000_0111);
+
+        channel.generate_sample(&mut mmu);
+        assert!(channel.enabled);
+    }
+
+    #[test]
+    fn test_sweep_clearing_negate_disables() {
+        let mut mmu = Mmu::init();
+        let mut channel = channel_1();
+        mmu.set(0xFF10, 0b0000_1001);
+        mmu.set(0xFF12, 0xF0);
+        mmu.set(0xFF13, 0x00);
+        mmu.set(0xFF14, 0b1000_0100);
+
+        channel.generate_sample(&mut mmu);
+        assert!(channel.enabled);
+
+        mmu.set(0xFF10, 0b0000_0001);
+        channel.generate_sample(&mut mmu);
+        assert!(!channel.enabled);
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CheatError {
+    InvalidFormat,
+    InvalidAddress(u16),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidFormat => write!(
+                f,
+                "Expected a Game Genie code (ABC-DEF or ABC-DEF-GHI) or a GameShark code (ABCDEFGH)"
+            ),
+            CheatError::InvalidAddress(address) => {
+                write!(f, "Cheat address {address:#06x} is out of range")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+// Replaces a ROM byte, optionally only while the original byte matches
+// `compare` so the patch doesn't hit other banks
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct GameGenieCode {
+    address: u16,
+    value: u8,
+    compare: Option<u8>,
+}
+
+// Written to RAM once a frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct GameSharkCode {
+    address: u16,
+    value: u8,
+}
+
+fn hex_digits(code: &str) -> Option<Vec<u8>> {
+    code.chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect()
+}
+
+// ABC-DEF-GHI: AB is the new value, FCDE the address with F inverted, and
+// GI the compare value rotated and scrambled. H isn't used.
+fn parse_game_genie(code: &str) -> Result<GameGenieCode, CheatError> {
+    let groups: Vec<&str> = code.split('-').collect();
+    if !(2..=3).contains(&groups.len()) || groups.iter().any(|g| g.len() != 3) {
+        return Err(CheatError::InvalidFormat);
+    }
+    let d = hex_digits(&groups.concat()).ok_or(CheatError::InvalidFormat)?;
+    let value = d[0] << 4 | d[1];
+    let address =
+        ((d[5] ^ 0xF) as u16) << 12 | (d[2] as u16) << 8 | (d[3] as u16) << 4 | d[4] as u16;
+    if address >= 0x8000 {
+        return Err(CheatError::InvalidAddress(address));
+    }
+    let compare = (d.len() == 9).then(|| (d[6] << 4 | d[8]).rotate_right(2) ^ 0xBA);
+    Ok(GameGenieCode {
+        address,
+        value,
+        compare,
+    })
+}
+
+// ABCDEFGH: AB is the RAM bank (ignored), CD the value and GHEF the address
+fn parse_game_shark(code: &str) -> Result<GameSharkCode, CheatError> {
+    if code.len() != 8 {
+        return Err(CheatError::InvalidFormat);
+    }
+    let d = hex_digits(code).ok_or(CheatError::InvalidFormat)?;
+    let value = d[2] << 4 | d[3];
+    let address = u16::from_le_bytes([d[4] << 4 | d[5], d[6] << 4 | d[7]]);
+    if !(0xA000..=0xDFFF).contains(&address) {
+        return Err(CheatError::InvalidAddress(address));
+    }
+    Ok(GameSharkCode { address, value })
+}
+
+#[derive(Debug, Default)]
+pub struct CheatEngine {
+    game_genie: Vec<GameGenieCode>,
+    game_shark: Vec<GameSharkCode>,
+}
+
+impl CheatEngine {
+    // Codes with dashes are Game Genie codes, anything else is GameShark
+    pub fn add(&mut self, code: &str) -> Result<(), CheatError> {
+        let code = code.trim();
+        if code.contains('-') {
+            self.game_genie.push(parse_game_genie(code)?);
+        } else {
+            self.game_shark.push(parse_game_shark(code)?);
+        }
+        Ok(())
+    }
+
+    // The value the CPU sees when reading `value` from ROM at `address`
+    pub fn patch_rom_read(&self, address: u16, value: u8) -> u8 {
+        self.game_genie
+            .iter()
+            .find(|code| code.address == address && code.compare.is_none_or(|c| c == value))
+            .map_or(value, |code| code.value)
+    }
+
+    pub fn ram_writes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.game_shark
+            .iter()
+            .map(|code| (code.address, code.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_genie() {
+        assert_eq!(
+            parse_game_genie("00A-17B-C49"),
+            Ok(GameGenieCode {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            })
+        );
+        assert_eq!(
+            parse_game_genie("3E0-14F"),
+            Ok(GameGenieCode {
+                address: 0x0014,
+                value: 0x3E,
+                compare: None,
+            })
+        );
+        assert_eq!(
+            parse_game_genie("00A-17B-C4"),
+            Err(CheatError::InvalidFormat)
+        );
+        assert_eq!(
+            parse_game_genie("00A-17G-C49"),
+            Err(CheatError::InvalidFormat)
+        );
+        assert_eq!(
+            parse_game_genie("00A-177"),
+            Err(CheatError::InvalidAddress(0x8A17))
+        );
+    }
+
+    #[test]
+    fn test_parse_game_shark() {
+        assert_eq!(
+            parse_game_shark("01FF10C6"),
+            Ok(GameSharkCode {
+                address: 0xC610,
+                value: 0xFF,
+            })
+        );
+        assert_eq!(parse_game_shark("01FF10C"), Err(CheatError::InvalidFormat));
+        assert_eq!(
+            parse_game_shark("01FF0080"),
+            Err(CheatError::InvalidAddress(0x8000))
+        );
+    }
+
+    #[test]
+    fn test_patch_rom_read_compares() {
+        let mut cheats = CheatEngine::default();
+        cheats.add("00A-17B-C49").unwrap();
+        assert_eq!(cheats.patch_rom_read(0x4A17, 0xC8), 0x00);
+        assert_eq!(cheats.patch_rom_read(0x4A17, 0xC9), 0xC9);
+        assert_eq!(cheats.patch_rom_read(0x4A18, 0xC8), 0xC8);
+    }
+}
@@ -1,7 +1,12 @@
+use std::fmt;
 use std::usize;
 
-use crate::interrupts::{get_interrupts, Interrupt};
-use crate::mmu::Mmu;
+use crate::disassembler::disassemble_instr;
+
+use crate::core::interrupts::{get_interrupts, Interrupt};
+use crate::core::mmu::Mmu;
+use crate::core::registers::KEY1;
+use crate::core::symbols::Location;
 
 const CLOCK_SPEED: u64 = 1_050_000;
 const DIV_RATE: u64 = 16_384;
@@ -29,6 +34,11 @@ fn dec_8_8(x: u8, y: u8) -> (u8, u8) {
     (a, b)
 }
 
+// True if any button in the currently selected joypad group is held
+fn joypad_pressed(mem: &Mmu) -> bool {
+    mem.get(0xFF00) & 0x0F != 0x0F
+}
+
 fn flag_to_u8(x: bool) -> u8 {
     if x {
         1u8
@@ -71,6 +81,18 @@ impl Default for Registers {
 }
 
 impl Registers {
+    fn zeroed() -> Self {
+        Registers {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+        }
+    }
+
     fn get_bc(&self) -> u16 {
         let b = self.b as u16;
         let c = self.c as u16;
@@ -137,7 +159,82 @@ pub struct Cpu {
     ime_delay: bool,
     pub halted: bool,
     pub stopped: bool,
-    clock_cycles: usize,
+    pub double_speed: bool,
+    profile: Profile,
+    breakpoints: Breakpoints,
+    // The breakpoint hit since the last `take_break`
+    break_hit: Option<BreakCause>,
+}
+
+// Opcodes and interrupts the debugger stops on
+#[derive(Debug, Clone, Copy)]
+struct Breakpoints {
+    opcodes: [bool; 256],
+    // As IF bits
+    interrupts: u8,
+    // Stop the first time an instruction leaves PC here, then forget it
+    run_to: Option<Location>,
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Breakpoints {
+            opcodes: [false; 256],
+            interrupts: 0,
+            run_to: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakCause {
+    Opcode { opcode: u8, pc: u16 },
+    Interrupt(Interrupt),
+    Address(u16),
+}
+
+impl fmt::Display for BreakCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakCause::Opcode { opcode, pc } => {
+                write!(f, "Break: opcode {opcode:02X} ran at {pc:#06X}")
+            }
+            BreakCause::Interrupt(interrupt) => write!(f, "Break: {interrupt:?} interrupt"),
+            BreakCause::Address(address) => write!(f, "Break: reached {address:#06X}"),
+        }
+    }
+}
+
+// Per-opcode execution counts, only updated while profiling is enabled
+#[derive(Debug, Clone, Copy)]
+struct Profile {
+    enabled: bool,
+    opcodes: [u64; 256],
+    cb_opcodes: [u64; 256],
+    cycles: u64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            enabled: false,
+            opcodes: [0; 256],
+            cb_opcodes: [0; 256],
+            cycles: 0,
+        }
+    }
+}
+
+// Nonzero counts as (opcode, count), most frequent first
+fn sorted_counts(counts: &[u64; 256]) -> Vec<(u8, u64)> {
+    let mut result: Vec<(u8, u64)> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(opcode, &count)| (opcode as u8, count))
+        .collect();
+    result.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    result
 }
 
 impl Default for Cpu {
@@ -156,8 +253,141 @@ impl Default for Cpu {
             ime_delay: false,
             halted: false,
             stopped: false,
-            clock_cycles: 0,
-        }
+            double_speed: false,
+            profile: Profile::default(),
+            breakpoints: Breakpoints::default(),
+            break_hit: None,
+        }
+    }
+}
+
+// Snapshot of the CPU state before an instruction, for tracing and comparing
+// against logs from other emulators
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CpuTrace {
+    pub a: u8,
+    // Flag bits, Z N H C in bits 7 to 4
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    // The opcode byte at PC followed by the next three bytes
+    pub bytes: [u8; 4],
+}
+
+impl fmt::Display for CpuTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})",
+            self.a,
+            self.f,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc,
+            self.bytes[0],
+            self.bytes[1],
+            self.bytes[2],
+            self.bytes[3]
+        )
+    }
+}
+
+// A register or register pair, for the debugger to read and write
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Reg {
+    pub fn from_name(name: &str) -> Option<Reg> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Reg::A),
+            "f" => Some(Reg::F),
+            "b" => Some(Reg::B),
+            "c" => Some(Reg::C),
+            "d" => Some(Reg::D),
+            "e" => Some(Reg::E),
+            "h" => Some(Reg::H),
+            "l" => Some(Reg::L),
+            "af" => Some(Reg::AF),
+            "bc" => Some(Reg::BC),
+            "de" => Some(Reg::DE),
+            "hl" => Some(Reg::HL),
+            "sp" => Some(Reg::SP),
+            "pc" => Some(Reg::PC),
+            _ => None,
+        }
+    }
+}
+
+// A copy of the registers, read with `Cpu::registers`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RegisterSnapshot {
+    pub a: u8,
+    // Flag bits, Z N H C in bits 7 to 4
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl RegisterSnapshot {
+    pub fn get(&self, reg: Reg) -> u16 {
+        let pair = |high: u8, low: u8| (high as u16) << 8 | low as u16;
+        match reg {
+            Reg::A => self.a as u16,
+            Reg::F => self.f as u16,
+            Reg::B => self.b as u16,
+            Reg::C => self.c as u16,
+            Reg::D => self.d as u16,
+            Reg::E => self.e as u16,
+            Reg::H => self.h as u16,
+            Reg::L => self.l as u16,
+            Reg::AF => pair(self.a, self.f),
+            Reg::BC => pair(self.b, self.c),
+            Reg::DE => pair(self.d, self.e),
+            Reg::HL => pair(self.h, self.l),
+            Reg::SP => self.sp,
+            Reg::PC => self.pc,
+        }
+    }
+
+    // The flags as letters, with `-` for each one that's clear, e.g. `Z-H-`
+    pub fn flags(&self) -> String {
+        "ZNHC"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| if self.f & (0x80 >> i) != 0 { flag } else { '-' })
+            .collect()
     }
 }
 
@@ -316,7 +546,7 @@ fn ld_a_r16_mem(pair: R16Mem, cpu: &mut Cpu, mem: &mut Mmu) {
 
 fn ld_imm16_sp(cpu: &mut Cpu, mem: &mut Mmu, addr: u16) {
     mem.set(addr, (cpu.sp & 0xFF) as u8);
-    mem.set(addr + 1, (cpu.sp >> 8) as u8);
+    mem.set(addr.wrapping_add(1), (cpu.sp >> 8) as u8);
 }
 
 fn inc_r16(cpu: &mut Cpu, opcode: u8) {
@@ -331,7 +561,7 @@ fn inc_r16(cpu: &mut Cpu, opcode: u8) {
             (cpu.registers.h, cpu.registers.l) = inc_8_8(cpu.registers.h, cpu.registers.l);
         }
         R16::SP => {
-            cpu.sp += 1;
+            cpu.inc_sp();
         }
     }
 }
@@ -348,7 +578,7 @@ fn dec_r16(cpu: &mut Cpu, opcode: u8) {
             (cpu.registers.h, cpu.registers.l) = dec_8_8(cpu.registers.h, cpu.registers.l);
         }
         R16::SP => {
-            cpu.sp = cpu.sp.wrapping_sub(1);
+            cpu.dec_sp();
         }
     }
 }
@@ -382,8 +612,9 @@ fn inc_r8(cpu: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
             cpu.registers.l = result;
         }
         R8::HLMem => {
-            result = mem[cpu.registers.get_hl() as usize].wrapping_add(1);
-            mem[cpu.registers.get_hl() as usize] = result;
+            // Read and write back through the bus like any other access
+            result = mem.get(cpu.registers.get_hl() as usize).wrapping_add(1);
+            mem.set(cpu.registers.get_hl(), result);
             cycles += 2;
         }
         R8::A => {
@@ -426,7 +657,7 @@ fn dec_r8(cpu: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
             cpu.registers.l = result;
         }
         R8::HLMem => {
-            result = mem[cpu.registers.get_hl() as usize].wrapping_sub(1);
+            result = mem.get(cpu.registers.get_hl() as usize).wrapping_sub(1);
             mem.set(cpu.registers.get_hl(), result);
             cycles += 2;
         }
@@ -497,8 +728,8 @@ fn rotate_right(state: &mut Cpu, through_carry_flag: bool, val: u8) -> u8 {
 
 fn jr(state: &mut Cpu, mem: &Mmu) {
     let val = mem.get(state.pc + 1) as i8;
-    state.pc += 2;
-    state.pc = state.pc.wrapping_add_signed(val.into());
+    state.advance_pc(2);
+    state.pc = state.pc.wrapping_add_signed(val.into()) & 0xFFFF;
 }
 
 fn jp(state: &mut Cpu, mem: &Mmu) {
@@ -512,7 +743,7 @@ fn jr_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jr(state, mem);
                 3
             } else {
-                state.pc += 2;
+                state.advance_pc(2);
                 2
             }
         }
@@ -521,7 +752,7 @@ fn jr_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jr(state, mem);
                 3
             } else {
-                state.pc += 2;
+                state.advance_pc(2);
                 2
             }
         }
@@ -530,7 +761,7 @@ fn jr_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jr(state, mem);
                 3
             } else {
-                state.pc += 2;
+                state.advance_pc(2);
                 2
             }
         }
@@ -539,7 +770,7 @@ fn jr_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jr(state, mem);
                 3
             } else {
-                state.pc += 2;
+                state.advance_pc(2);
                 2
             }
         }
@@ -553,7 +784,7 @@ fn jp_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jp(state, mem);
                 4
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -562,7 +793,7 @@ fn jp_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jp(state, mem);
                 4
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -571,7 +802,7 @@ fn jp_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jp(state, mem);
                 4
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -580,14 +811,16 @@ fn jp_cond(state: &mut Cpu, mem: &Mmu, opcode: u8) -> u64 {
                 jp(state, mem);
                 4
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
     }
 }
 
-fn get_register_value(state: &mut Cpu, mem: &Mmu, register: R8) -> u8 {
+// Reading or writing (HL) costs an extra M-cycle, which callers count in the
+// cycles they return
+fn get_register_value(state: &Cpu, mem: &Mmu, register: R8) -> u8 {
     match register {
         R8::B => state.registers.b,
         R8::C => state.registers.c,
@@ -595,10 +828,7 @@ fn get_register_value(state: &mut Cpu, mem: &Mmu, register: R8) -> u8 {
         R8::E => state.registers.e,
         R8::H => state.registers.h,
         R8::L => state.registers.l,
-        R8::HLMem => {
-            state.clock_cycles += 1;
-            mem.get(state.registers.get_hl() as usize)
-        }
+        R8::HLMem => mem.get(state.registers.get_hl() as usize),
         R8::A => state.registers.a,
     }
 }
@@ -611,10 +841,7 @@ fn set_register_value(state: &mut Cpu, mem: &mut Mmu, register: R8, value: u8) {
         R8::E => state.registers.e = value,
         R8::H => state.registers.h = value,
         R8::L => state.registers.l = value,
-        R8::HLMem => {
-            mem.set(state.registers.get_hl(), value);
-            state.clock_cycles += 1;
-        }
+        R8::HLMem => mem.set(state.registers.get_hl(), value),
         R8::A => state.registers.a = value,
     }
 }
@@ -747,8 +974,11 @@ fn cp(state: &mut Cpu, val: u8) -> Flags {
 }
 
 fn ret(state: &mut Cpu, mem: &mut Mmu) {
-    state.pc = (mem.get(state.sp + 1) as usize) << 8 | mem.get(state.sp) as usize;
-    state.sp += 2;
+    let low = mem.get(state.sp) as usize;
+    state.inc_sp();
+    let high = mem.get(state.sp) as usize;
+    state.inc_sp();
+    state.pc = high << 8 | low;
 }
 
 fn ret_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
@@ -758,7 +988,7 @@ fn ret_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 ret(state, mem);
                 5
             } else {
-                state.pc += 1;
+                state.advance_pc(1);
                 2
             }
         }
@@ -767,7 +997,7 @@ fn ret_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 ret(state, mem);
                 5
             } else {
-                state.pc += 1;
+                state.advance_pc(1);
                 2
             }
         }
@@ -776,7 +1006,7 @@ fn ret_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 ret(state, mem);
                 5
             } else {
-                state.pc += 1;
+                state.advance_pc(1);
                 2
             }
         }
@@ -785,21 +1015,25 @@ fn ret_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 ret(state, mem);
                 5
             } else {
-                state.pc += 1;
+                state.advance_pc(1);
                 2
             }
         }
     }
 }
 
-fn call(state: &mut Cpu, mem: &mut Mmu) {
-    state.sp -= 1;
-    mem.set(state.sp as u16, ((state.pc + 3) >> 8) as u8);
-    state.sp -= 1;
-    mem.set(state.sp as u16, ((state.pc + 3) & 0xFF) as u8);
+// Push the address of the next instruction for `ret` to pop: high byte at
+// SP + 1, low byte at SP
+fn push_return_address(state: &mut Cpu, mem: &mut Mmu, address: usize) {
+    state.dec_sp();
+    mem.set(state.sp as u16, (address >> 8) as u8);
+    state.dec_sp();
+    mem.set(state.sp as u16, (address & 0xFF) as u8);
+}
 
+fn call(state: &mut Cpu, mem: &mut Mmu) {
+    push_return_address(state, mem, state.pc + 3);
     jp(state, mem);
-    state.clock_cycles += 6;
 }
 
 fn call_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
@@ -809,7 +1043,7 @@ fn call_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 call(state, mem);
                 6
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -818,7 +1052,7 @@ fn call_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 call(state, mem);
                 6
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -827,7 +1061,7 @@ fn call_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 call(state, mem);
                 6
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -836,7 +1070,7 @@ fn call_cond(state: &mut Cpu, mem: &mut Mmu, opcode: u8) -> u64 {
                 call(state, mem);
                 6
             } else {
-                state.pc += 3;
+                state.advance_pc(3);
                 3
             }
         }
@@ -853,27 +1087,27 @@ fn pop_r16stk(state: &mut Cpu, mem: &mut Mmu, opcode: u8) {
                 h: (f & 0b00100000) >> 5 == 1,
                 c: (f & 0b00010000) >> 4 == 1,
             };
-            state.sp += 1;
+            state.inc_sp();
             state.registers.a = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
         }
         R16Stk::BC => {
             state.registers.c = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
             state.registers.b = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
         }
         R16Stk::DE => {
             state.registers.e = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
             state.registers.d = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
         }
         R16Stk::HL => {
             state.registers.l = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
             state.registers.h = mem.get(state.sp);
-            state.sp += 1;
+            state.inc_sp();
         }
     }
 }
@@ -881,9 +1115,9 @@ fn pop_r16stk(state: &mut Cpu, mem: &mut Mmu, opcode: u8) {
 fn push_r16stk(state: &mut Cpu, mem: &mut Mmu, opcode: u8) {
     match r16stk((opcode & 0b00110000) >> 4) {
         R16Stk::AF => {
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.a);
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(
                 state.sp as u16,
                 flag_to_u8(state.flags.z) << 7
@@ -893,21 +1127,21 @@ fn push_r16stk(state: &mut Cpu, mem: &mut Mmu, opcode: u8) {
             );
         }
         R16Stk::BC => {
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.b);
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.c);
         }
         R16Stk::DE => {
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.d);
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.e);
         }
         R16Stk::HL => {
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.h);
-            state.sp -= 1;
+            state.dec_sp();
             mem.set(state.sp as u16, state.registers.l);
         }
     }
@@ -924,10 +1158,148 @@ impl Cpu {
             ime_delay: false,
             halted: false,
             stopped: false,
-            clock_cycles: 0,
+            double_speed: false,
+            profile: Profile::default(),
+            breakpoints: Breakpoints::default(),
+            break_hit: None,
+        }
+    }
+
+    // The power-on state, for running a boot ROM from 0x0000
+    pub fn boot() -> Self {
+        Cpu {
+            registers: Registers::zeroed(),
+            flags: Flags::default(),
+            pc: 0x0000,
+            sp: 0x0000,
+            ..Cpu::default()
         }
     }
 
+    // Back to the power-on state, keeping the profile and the debugger's
+    // breakpoints
+    pub fn reset(&mut self) {
+        *self = Cpu {
+            profile: self.profile,
+            breakpoints: self.breakpoints,
+            ..Cpu::default()
+        };
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.profile.enabled = true;
+    }
+
+    // Stop in the debugger every time `opcode` runs
+    pub fn add_opcode_breakpoint(&mut self, opcode: u8) {
+        self.breakpoints.opcodes[opcode as usize] = true;
+    }
+
+    // Stop in the debugger every time `interrupt` is serviced
+    pub fn add_interrupt_breakpoint(&mut self, interrupt: Interrupt) {
+        self.breakpoints.interrupts |= interrupt.mask();
+    }
+
+    // Stop once PC reaches `address`, for the debugger's `run-to`. Replaces
+    // any earlier target.
+    pub fn run_to(&mut self, location: Location) {
+        self.breakpoints.run_to = Some(location);
+    }
+
+    pub fn take_break(&mut self) -> Option<BreakCause> {
+        self.break_hit.take()
+    }
+
+    // Go back to an earlier state for the debugger's undo, keeping the
+    // breakpoints set since
+    pub fn rewind_to(&mut self, earlier: Cpu) {
+        *self = Cpu {
+            breakpoints: self.breakpoints,
+            ..earlier
+        };
+    }
+
+    // PC wraps at the 16-bit boundary like the hardware's
+    fn advance_pc(&mut self, n: usize) {
+        self.pc = self.pc.wrapping_add(n) & 0xFFFF;
+    }
+
+    // And so does SP, so pushing at 0x0000 writes to 0xFFFF
+    fn inc_sp(&mut self) {
+        self.sp = self.sp.wrapping_add(1) & 0xFFFF;
+    }
+
+    fn dec_sp(&mut self) {
+        self.sp = self.sp.wrapping_sub(1) & 0xFFFF;
+    }
+
+    // Opcodes executed while profiling, sorted by frequency
+    pub fn profile_report(&self) -> Vec<(u8, u64)> {
+        sorted_counts(&self.profile.opcodes)
+    }
+
+    // CB-prefixed opcodes executed while profiling, sorted by frequency
+    pub fn cb_profile_report(&self) -> Vec<(u8, u64)> {
+        sorted_counts(&self.profile.cb_opcodes)
+    }
+
+    pub fn profiled_cycles(&self) -> u64 {
+        self.profile.cycles
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn registers(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            a: self.registers.a,
+            f: self.get_f_register(),
+            b: self.registers.b,
+            c: self.registers.c,
+            d: self.registers.d,
+            e: self.registers.e,
+            h: self.registers.h,
+            l: self.registers.l,
+            sp: self.sp as u16,
+            pc: self.pc as u16,
+        }
+    }
+
+    // Set a register or pair. Single registers take the low byte of `value`,
+    // and the low nibble of F always reads 0.
+    pub fn set_register(&mut self, reg: Reg, value: u16) {
+        let byte = value as u8;
+        match reg {
+            Reg::A => self.registers.a = byte,
+            Reg::F => self.set_f_register(byte),
+            Reg::B => self.registers.b = byte,
+            Reg::C => self.registers.c = byte,
+            Reg::D => self.registers.d = byte,
+            Reg::E => self.registers.e = byte,
+            Reg::H => self.registers.h = byte,
+            Reg::L => self.registers.l = byte,
+            Reg::AF => {
+                self.registers.a = (value >> 8) as u8;
+                self.set_f_register(byte);
+            }
+            Reg::BC => self.registers.set_bc(value),
+            Reg::DE => self.registers.set_de(value),
+            Reg::HL => self.registers.set_hl(value),
+            Reg::SP => self.sp = value as usize,
+            Reg::PC => self.pc = value as usize,
+        }
+    }
+
+    fn set_f_register(&mut self, f: u8) {
+        self.flags = Flags {
+            z: f & 0x80 != 0,
+            n: f & 0x40 != 0,
+            h: f & 0x20 != 0,
+            c: f & 0x10 != 0,
+        };
+    }
+
     fn get_f_register(&self) -> u8 {
         let b7 = if self.flags.z { 1 } else { 0 };
         let b6 = if self.flags.n { 1 } else { 0 };
@@ -936,6 +1308,13 @@ impl Cpu {
         b7 << 7 | b6 << 6 | b5 << 5 | b4 << 4
     }
 
+    // Leave STOP mode once one of the selected joypad buttons is held
+    pub fn resume_from_stop(&mut self, mem: &Mmu) {
+        if self.stopped && joypad_pressed(mem) {
+            self.stopped = false;
+        }
+    }
+
     pub fn enable_ime_delayed(&mut self) {
         if self.ime_delay {
             self.ime = true;
@@ -945,12 +1324,24 @@ impl Cpu {
 
     pub fn execute(&mut self, mem: &mut Mmu) -> u64 {
         let opcode = mem.get(self.pc);
+        if self.breakpoints.opcodes[opcode as usize] {
+            self.break_hit = Some(BreakCause::Opcode {
+                opcode,
+                pc: self.pc as u16,
+            });
+        }
+        if self.profile.enabled {
+            self.profile.opcodes[opcode as usize] += 1;
+            if opcode == 0xCB {
+                self.profile.cb_opcodes[mem.get(self.pc + 1) as usize] += 1;
+            }
+        }
         let clock_cycles;
         match opcode {
             // NOP
             0x00 => {
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // ld r16, imm16
             op if 0b11001111 & op == 0b00000001 => {
@@ -959,21 +1350,21 @@ impl Cpu {
                 ld_r16(register_pair, self, imm16);
 
                 clock_cycles = 3;
-                self.pc += 3;
+                self.advance_pc(3);
             }
             // ld [r16mem], a
             op if 0b11001111 & op == 0b00000010 => {
                 ld_r16_mem_a(r16_mem((op & 0b00110000) >> 4), self, mem);
 
                 clock_cycles = 2;
-                self.pc += 1
+                self.advance_pc(1)
             }
             // ld a, [r16mem]
             op if 0b11001111 & op == 0b00001010 => {
                 ld_a_r16_mem(r16_mem((op & 0b00110000) >> 4), self, mem);
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // ld [imm16], sp
             0x08 => {
@@ -984,21 +1375,21 @@ impl Cpu {
                 );
 
                 clock_cycles = 5;
-                self.pc += 3;
+                self.advance_pc(3);
             }
             // inc r16
             op if 0b11001111 & op == 0b00000011 => {
                 inc_r16(self, op);
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // dec r16
             op if 0b11001111 & op == 0b00001011 => {
                 dec_r16(self, op);
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // add hl, r16
             op if 0b11001111 & op == 0b00001001 => {
@@ -1042,22 +1433,22 @@ impl Cpu {
                 }
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // INC r8
             op if 0b11000111 & op == 0b00000100 => {
                 clock_cycles = inc_r8(self, mem, op);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // DEC r8
             op if 0b11000111 & op == 0b00000101 => {
                 clock_cycles = dec_r8(self, mem, op);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // LD r8, imm8
             op if 0b11000111 & op == 0b00000110 => {
                 clock_cycles = ld_r8_imm(self, mem, op, mem.get(self.pc + 1));
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // RLCA
             0x07 => {
@@ -1067,7 +1458,7 @@ impl Cpu {
                 self.flags.h = false;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // RRCA
             0x0F => {
@@ -1077,7 +1468,7 @@ impl Cpu {
                 self.flags.h = false;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // RLA
             0x17 => {
@@ -1087,7 +1478,7 @@ impl Cpu {
                 self.flags.h = false;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // RRA
             0x1F => {
@@ -1097,32 +1488,14 @@ impl Cpu {
                 self.flags.h = false;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // DAA
-            // Code adapted from https://forums.nesdev.org/viewtopic.php?p=196282&sid=b1d399755b0f63e5d709a5d21bf1492e#p196282
             0x27 => {
-                if !self.flags.n {
-                    if self.flags.c || self.registers.a > 0x99 {
-                        self.registers.a = self.registers.a.wrapping_add(0x60);
-                        self.flags.c = true;
-                    }
-                    if self.flags.h || (self.registers.a & 0x0F) > 0x09 {
-                        self.registers.a = self.registers.a.wrapping_add(0x6);
-                    }
-                } else {
-                    if self.flags.c {
-                        self.registers.a = self.registers.a.wrapping_sub(0x60);
-                    }
-                    if self.flags.h {
-                        self.registers.a = self.registers.a.wrapping_sub(0x6);
-                    }
-                }
-                self.flags.z = self.registers.a == 0;
-                self.flags.h = false;
+                daa(self);
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // CPL
             0x2F => {
@@ -1131,7 +1504,7 @@ impl Cpu {
                 self.flags.h = true;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // SCF
             0x37 => {
@@ -1140,7 +1513,7 @@ impl Cpu {
                 self.flags.c = true;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // CCF
             0x3F => {
@@ -1149,13 +1522,13 @@ impl Cpu {
                 self.flags.c = !self.flags.c;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // JR imm8
             0x18 => {
                 let val = mem.get(self.pc + 1) as i8;
-                self.pc += 2;
-                self.pc = self.pc.wrapping_add_signed(val.into());
+                self.advance_pc(2);
+                self.pc = self.pc.wrapping_add_signed(val.into()) & 0xFFFF;
 
                 clock_cycles = 3;
             }
@@ -1166,109 +1539,116 @@ impl Cpu {
             // STOP
             0x10 => {
                 mem.set(0xFF04, 0); // reset DIV register
-                self.pc += 2;
+                if mem.cgb_mode() && mem.get(KEY1) & 0b1 != 0 {
+                    // A prepared speed switch happens instead of stopping
+                    self.double_speed = !self.double_speed;
+                    mem.set(KEY1 as u16, if self.double_speed { 0x80 } else { 0x00 });
+                } else if !joypad_pressed(mem) {
+                    self.stopped = true;
+                }
+                self.advance_pc(2);
                 clock_cycles = 1;
             }
             // LD r8, r8
             op if 0b11000000 & op == 0b01000000 => {
                 clock_cycles = ld_r8_r8(self, mem, op);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // ADD A, r8
             op if 0b11111000 & op == 0b10000000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, add);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // ADC A, r8
             op if 0b11111000 & op == 0b10001000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, adc);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // SUB A, r8
             op if 0b11111000 & op == 0b10010000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, sub);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // SBC A, r8
             op if 0b11111000 & op == 0b10011000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, sbc);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // AND A, r8
             op if 0b11111000 & op == 0b10100000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, and_);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // XOR A, r8
             op if 0b11111000 & op == 0b10101000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, xor_);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // OR A, r8
             op if 0b11111000 & op == 0b10110000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, or_);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // CP A, r8
             op if 0b11111000 & op == 0b10111000 => {
                 (self.flags, clock_cycles) = operate(self, mem, op, cp);
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // ADD A, imm8
             0xC6 => {
                 self.flags = operate_imm(self, mem, add);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // ADC A, imm8
             0xCE => {
                 self.flags = operate_imm(self, mem, adc);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // SUB A, imm8
             0xD6 => {
                 self.flags = operate_imm(self, mem, sub);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // SBC A, imm8
             0xDE => {
                 self.flags = operate_imm(self, mem, sbc);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // AND A, imm8
             0xE6 => {
                 self.flags = operate_imm(self, mem, and_);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // XOR A, imm8
             0xEE => {
                 self.flags = operate_imm(self, mem, xor_);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // OR A, imm8
             0xF6 => {
                 self.flags = operate_imm(self, mem, or_);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // CP A, imm8
             0xFE => {
                 self.flags = operate_imm(self, mem, cp);
 
                 clock_cycles = 2;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // RET COND
             op if 0b11100111 & op == 0b11000000 => {
@@ -1312,11 +1692,7 @@ impl Cpu {
             }
             // RST tgt3
             op if 0b11000111 & op == 0b11000111 => {
-                self.sp -= 1;
-                mem.set(self.sp as u16, (((self.pc + 1) & 0xFF00) >> 8) as u8);
-                self.sp -= 1;
-                mem.set(self.sp as u16, ((self.pc + 1) & 0xFF) as u8);
-
+                push_return_address(self, mem, self.pc + 1);
                 clock_cycles = 4;
                 self.pc = (0b00111000 & op) as usize;
             }
@@ -1325,21 +1701,21 @@ impl Cpu {
                 pop_r16stk(self, mem, op);
 
                 clock_cycles = 3;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // PUSH R16stk
             op if 0b11001111 & op == 0b11000101 => {
                 push_r16stk(self, mem, op);
 
                 clock_cycles = 4;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // LDH [C], A
             0xE2 => {
                 mem.set(0xFF00 + self.registers.c as u16, self.registers.a);
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // LDH [imm8], A
             0xE0 => {
@@ -1347,7 +1723,7 @@ impl Cpu {
                 mem.set(0xFF00 + addr, self.registers.a);
 
                 clock_cycles = 3;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // LD [imm16], A
             0xEA => {
@@ -1355,21 +1731,21 @@ impl Cpu {
                 mem.set(addr, self.registers.a);
 
                 clock_cycles = 4;
-                self.pc += 3;
+                self.advance_pc(3);
             }
             // LDH A, [C]
             0xF2 => {
                 self.registers.a = mem.get(0xFF00 + self.registers.c as usize);
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // LDH A, [imm8]
             0xF0 => {
                 self.registers.a = mem.get(0xFF00 + mem[self.pc + 1] as usize);
 
                 clock_cycles = 3;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // LD A, [imm16]
             0xFA => {
@@ -1377,118 +1753,176 @@ impl Cpu {
                     mem.get((mem.get(self.pc + 2) as usize) << 8 | mem.get(self.pc + 1) as usize);
 
                 clock_cycles = 4;
-                self.pc += 3;
+                self.advance_pc(3);
             }
             // ADD SP, imm8
             0xE8 => {
-                let diff = mem[self.pc + 1] as i8;
-                let prev = self.sp as u16;
-                let result = prev.wrapping_add_signed(diff.into());
-                self.sp = result as usize;
-                self.flags.z = false;
-                self.flags.n = false;
-                self.flags.h = if diff >= 0 {
-                    (prev & 0xF) + ((diff as u16) & 0xF) > 0xF
-                } else {
-                    ((prev as usize) & 0x0F) >= ((-diff) as usize & 0x0F)
-                    // ((prev & 0x0F).wrapping_sub(diff as u16 & 0x0F)) & 0x10 != 0
-                };
-                self.flags.c = if diff >= 0 {
-                    (prev & 0xFF) + ((diff as u16) & 0xFF) > 0xFF
-                } else {
-                    ((prev as usize) & 0xFF) >= (((-diff) as usize) & 0xFF)
-                };
-                // if diff == -1 {
-                //     println!(
-                //         "a: {:#06x}, a - 1: {:#06x}, c: {}, h: {}",
-                //         prev,
-                //         result,
-                //         if self.flags.c { 1 } else { 0 },
-                //         if self.flags.h { 1 } else { 0 }
-                //     );
-                // }
+                self.sp = add_sp_e8(self, mem[self.pc + 1]) as usize;
                 clock_cycles = 4;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // LD HL, SP + imm8
             0xF8 => {
-                let diff = mem[self.pc + 1] as i8;
-                let prev = self.sp;
-                let result = prev.wrapping_add_signed(diff.into());
-                self.registers.set_hl(result as u16);
-                self.flags.z = false;
-                self.flags.n = false;
-                self.flags.h = if diff >= 0 {
-                    (prev & 0xF) + ((diff as usize) & 0xF) > 0xF
-                } else {
-                    ((prev as usize) & 0x0F) >= ((-diff) as usize & 0x0F)
-                    // ((prev & 0x0F).wrapping_sub(diff as usize & 0x0F)) & 0x10 != 0
-                };
-                self.flags.c = if diff >= 0 {
-                    (prev & 0xFF) + ((diff as usize) & 0xFF) > 0xFF
-                } else {
-                    ((prev as usize) & 0xFF) >= (((-diff) as usize) & 0xFF)
-                };
-
+                let result = add_sp_e8(self, mem[self.pc + 1]);
+                self.registers.set_hl(result);
                 clock_cycles = 3;
-                self.pc += 2;
+                self.advance_pc(2);
             }
             // LD SP, HL
             0xF9 => {
                 self.sp = self.registers.get_hl() as usize;
 
                 clock_cycles = 2;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // DI
             0xF3 => {
                 self.ime = false;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             // EI
             0xFB => {
                 self.ime_delay = true;
 
                 clock_cycles = 1;
-                self.pc += 1;
+                self.advance_pc(1);
             }
             0xCB => {
                 clock_cycles = execute_prefix_cb(self, mem);
 
-                self.pc += 1;
+                self.advance_pc(1);
             }
             op => {
                 panic!("Unrecognized opcode {:#02x}", op);
             }
         }
+        if self.profile.enabled {
+            self.profile.cycles += clock_cycles * 4;
+        }
+        self.check_run_to(mem);
         clock_cycles * 4
     }
 
+    // Stop if PC just reached the `run_to` target, by an instruction or an
+    // interrupt
+    fn check_run_to(&mut self, mem: &Mmu) {
+        if let Some(location) = self.breakpoints.run_to {
+            if location.contains(mem, self.pc as u16) {
+                self.breakpoints.run_to = None;
+                self.break_hit = Some(BreakCause::Address(self.pc as u16));
+            }
+        }
+    }
+
     pub fn handle_interrupt(&mut self, mem: &mut Mmu, interrupt: &Interrupt) {
+        if *interrupt & self.breakpoints.interrupts {
+            self.break_hit = Some(BreakCause::Interrupt(*interrupt));
+        }
         interrupt.clear(mem);
         self.ime = false;
-        self.sp -= 2;
-        mem.set(self.sp as u16, (self.pc & 0xFF) as u8);
-        mem.set(self.sp as u16 + 1, (self.pc >> 8) as u8);
+        push_return_address(self, mem, self.pc);
         self.pc = interrupt.address() as usize;
+        self.check_run_to(mem);
     }
 
+    // A requested interrupt that's also enabled in IE ends HALT, but it's only
+    // serviced if IME is set. Otherwise the CPU carries on after the HALT and
+    // the request stays pending.
     pub fn handle_interrupts(&mut self, mem: &mut Mmu) {
-        for interrupt in get_interrupts(mem) {
-            self.halted = false;
-            if self.ime && interrupt.enabled(mem) {
-                self.handle_interrupt(mem, &interrupt);
-            }
+        let Some(interrupt) = get_interrupts(mem)
+            .into_iter()
+            .find(|interrupt| interrupt.enabled(mem))
+        else {
+            return;
+        };
+        self.halted = false;
+        if self.ime {
+            self.handle_interrupt(mem, &interrupt);
+        }
+    }
+
+    pub fn trace(&self, mem: &Mmu) -> CpuTrace {
+        CpuTrace {
+            a: self.registers.a,
+            f: self.get_f_register(),
+            b: self.registers.b,
+            c: self.registers.c,
+            d: self.registers.d,
+            e: self.registers.e,
+            h: self.registers.h,
+            l: self.registers.l,
+            sp: self.sp as u16,
+            pc: self.pc as u16,
+            bytes: [0, 1, 2, 3].map(|i| mem[(self.pc + i) & 0xFFFF]),
+        }
+    }
+
+    // Execute one instruction, returning the state it started from and the
+    // number of clock cycles it took
+    pub fn step(&mut self, mem: &mut Mmu) -> (CpuTrace, u64) {
+        let trace = self.trace(mem);
+        let cycles = self.execute(mem);
+        (trace, cycles)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc as u16
+    }
+
+    // The registers and the disassembled instruction at PC, on one line
+    pub fn state_line(&self, mem: &Mmu) -> String {
+        let trace = self.trace(mem);
+        match disassemble_instr(&trace.bytes) {
+            Ok((instr, _)) => format!("{trace} {instr}"),
+            Err(_) => trace.to_string(),
         }
     }
 
     pub fn log_state(&self, mem: &Mmu) {
-        println!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})", self.registers.a, self.get_f_register(), self.registers.b, self.registers.c, self.registers.d, self.registers.e, self.registers.h, self.registers.l, self.sp, self.pc, mem[self.pc], mem[self.pc + 1], mem[self.pc + 2], mem[self.pc + 3]);
+        println!("{}", self.state_line(mem));
     }
 }
 
+// SP plus a signed offset, for ADD SP, e8 and LD HL, SP + e8. H and C come
+// from adding the offset's byte to SP's low byte as unsigned numbers, whatever
+// its sign.
+fn add_sp_e8(state: &mut Cpu, offset: u8) -> u16 {
+    let sp = state.sp as u16;
+    let low = sp & 0xFF;
+    let offset = offset as u16;
+    state.flags.z = false;
+    state.flags.n = false;
+    state.flags.h = (low & 0x0F) + (offset & 0x0F) > 0x0F;
+    state.flags.c = low + offset > 0xFF;
+    sp.wrapping_add_signed(offset as u8 as i8 as i16)
+}
+
+// Code adapted from https://forums.nesdev.org/viewtopic.php?p=196282&sid=b1d399755b0f63e5d709a5d21bf1492e#p196282
+// After an addition both nibbles are corrected and C is set if the high one
+// was; after a subtraction only H and C say what to undo, and C is kept.
+fn daa(state: &mut Cpu) {
+    if !state.flags.n {
+        if state.flags.c || state.registers.a > 0x99 {
+            state.registers.a = state.registers.a.wrapping_add(0x60);
+            state.flags.c = true;
+        }
+        if state.flags.h || (state.registers.a & 0x0F) > 0x09 {
+            state.registers.a = state.registers.a.wrapping_add(0x6);
+        }
+    } else {
+        if state.flags.c {
+            state.registers.a = state.registers.a.wrapping_sub(0x60);
+        }
+        if state.flags.h {
+            state.registers.a = state.registers.a.wrapping_sub(0x6);
+        }
+    }
+    state.flags.z = state.registers.a == 0;
+    state.flags.h = false;
+}
+
 fn sla_r8(state: &mut Cpu, val: u8) -> u8 {
     let new_val = val << 1;
     state.flags.z = new_val == 0;
@@ -1560,7 +1994,7 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
             state.flags.h = false;
             state.flags.z = new_val == 0;
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // RRC r8
         op if 0b11111000 & op == 0b00001000 => {
@@ -1571,7 +2005,7 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
             state.flags.h = false;
             state.flags.z = new_val == 0;
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // RL r8
         op if 0b11111000 & op == 0b00010000 => {
@@ -1582,7 +2016,7 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
             state.flags.h = false;
             state.flags.z = new_val == 0;
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // RR r8
         op if 0b11111000 & op == 0b00011000 => {
@@ -1593,35 +2027,35 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
             state.flags.h = false;
             state.flags.z = new_val == 0;
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // SLA r8
         op if 0b11111000 & op == 0b00100000 => {
             let new_val = sla_r8(state, val);
             set_register_value(state, mem, operand, new_val);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // SRA r8
         op if 0b11111000 & op == 0b00101000 => {
             let new_val = sra_r8(state, val);
             set_register_value(state, mem, operand, new_val);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // SWAP r8
         op if 0b11111000 & op == 0b00110000 => {
             let new_val = swap_r8(state, val);
             set_register_value(state, mem, operand, new_val);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // SRL r8
         op if 0b11111000 & op == 0b00111000 => {
             let new_val = srl_r8(state, val);
             set_register_value(state, mem, operand, new_val);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // BIT b, r8
         op if 0b11000000 & op == 0b01000000 => {
@@ -1629,19 +2063,19 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
 
             state.flags.n = false;
             state.flags.h = true;
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // RES b, r8
         op if 0b11000000 & op == 0b10000000 => {
             res(state, mem, op);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         // SET b, r8
         op if 0b11000000 & op == 0b11000000 => {
             set(state, mem, op);
 
-            state.pc += 1;
+            state.advance_pc(1);
         }
         _ => {
             panic!("Unrecognized opcode {:#02x}", opcode);
@@ -1734,6 +2168,52 @@ fn execute_prefix_cb(state: &mut Cpu, mem: &mut Mmu) -> u64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_trace_display() {
+        let mut mem = Mmu::init_with_vec(vec![0x04, 0x00, 0x00, 0x00]);
+        let mut state: Cpu = Default::default();
+
+        let (trace, cycles) = state.step(&mut mem);
+        assert_eq!(cycles, 4);
+        assert_eq!(trace.pc, 0x100);
+        assert_eq!(trace.bytes, [0x04, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            trace.to_string(),
+            "A: 01 F: B0 B: 00 C: 13 D: 00 E: D8 H: 01 L: 4D SP: FFFE PC: 00:0100 (04 00 00 00)"
+        );
+        assert_eq!(state.trace(&mem).b, 0x01);
+    }
+
+    #[test]
+    fn test_stop_without_button_stops() {
+        let mut mem = Mmu::init_with_vec(vec![0x10, 0x00]);
+        let mut state: Cpu = Default::default();
+
+        mem.set(0xFF00, 0x20);
+        state.execute(&mut mem);
+        assert!(state.stopped);
+        assert_eq!(state.pc, 0x102);
+        assert_eq!(mem.get(0xFF04), 0);
+
+        state.resume_from_stop(&mem);
+        assert!(state.stopped);
+    }
+
+    #[test]
+    fn test_stop_cgb_speed_switch() {
+        let mut rom = vec![0x10, 0x00];
+        rom.resize(0x44, 0);
+        rom[0x43] = 0x80;
+        let mut mem = Mmu::init_with_vec(rom);
+        let mut state: Cpu = Default::default();
+
+        mem.set(KEY1 as u16, 0x01);
+        state.execute(&mut mem);
+        assert!(!state.stopped);
+        assert!(state.double_speed);
+        assert_eq!(mem.get(KEY1), 0xFE);
+    }
+
     #[test]
     fn test_inc_8_8() {
         assert_eq!((0x01, 0x00), inc_8_8(0x00, 0xFF));
@@ -1745,11 +2225,11 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.flags.z = false;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.pc, 0x104);
 
         let mut state2: Cpu = Default::default();
-        execute(&mut state2, &mut mem);
+        state2.execute(&mut mem);
         assert_eq!(state2.pc, 0x102);
     }
 
@@ -1759,7 +2239,7 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.registers.b = 0xAB;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.d, 0xAB);
     }
 
@@ -1768,10 +2248,10 @@ mod tests {
         let mut mem = Mmu::init_with_vec(vec![0x70, 0x00, 0x00, 0x00]);
         let mut state: Cpu = Default::default();
 
-        state.registers.set_hl(0x0003);
+        state.registers.set_hl(0xC000);
         state.registers.b = 0xAB;
-        execute(&mut state, &mut mem);
-        assert_eq!(mem[3], 0xAB);
+        state.execute(&mut mem);
+        assert_eq!(mem[0xC000], 0xAB);
     }
 
     #[test]
@@ -1781,7 +2261,7 @@ mod tests {
 
         state.registers.a = 5;
         state.registers.b = 4;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.a, 9);
         assert!(!state.flags.z);
         assert!(!state.flags.n);
@@ -1790,7 +2270,7 @@ mod tests {
         let mut state2: Cpu = Default::default();
         state2.registers.a = 0xFF;
         state2.registers.b = 1;
-        execute(&mut state2, &mut mem);
+        state2.execute(&mut mem);
         assert!(state2.flags.z);
         assert!(state2.flags.c);
     }
@@ -1802,7 +2282,7 @@ mod tests {
 
         state.registers.a = 5;
         state.registers.b = 4;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.a, 1);
         assert!(state.flags.n);
     }
@@ -1814,7 +2294,7 @@ mod tests {
 
         state.registers.a = 0b00111100;
         state.registers.b = 0b00001100;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.a, 0b00001100);
     }
 
@@ -1825,7 +2305,7 @@ mod tests {
 
         state.registers.a = 0b00111100;
         state.registers.b = 0b00001100;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.a, 0b00111100);
     }
 
@@ -1836,12 +2316,12 @@ mod tests {
 
         state.sp = 0x0102;
         state.flags.z = false;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.pc, 0x3);
 
         let mut state2: Cpu = Default::default();
         state2.sp = 0x0102;
-        execute(&mut state2, &mut mem);
+        state2.execute(&mut mem);
         assert_eq!(state2.pc, 0x101);
     }
 
@@ -1851,7 +2331,7 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.registers.a = 0xAB;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(mem[0xFF0A], 0xAB);
     }
 
@@ -1860,7 +2340,7 @@ mod tests {
         let mut mem = Mmu::init_with_vec(vec![0x01, 0x12, 0x34]);
         let mut state: Cpu = Default::default();
 
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.b, 0x34);
         assert_eq!(state.registers.c, 0x12);
     }
@@ -1871,7 +2351,7 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.sp = 0x0102;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         println!("{:?}", state);
         assert_eq!(state.registers.get_bc(), 0x0A00);
     }
@@ -1880,23 +2360,23 @@ mod tests {
     fn test_ld_bcmem_a() {
         let mut mem = Mmu::init_with_vec(vec![0x02, 0x00, 0x00, 0x00]);
         let mut state: Cpu = Default::default();
-        state.registers.set_bc(0x0003);
+        state.registers.set_bc(0xC000);
         state.registers.a = 0xAB;
 
-        execute(&mut state, &mut mem);
-        assert_eq!(mem[3], 0xAB);
+        state.execute(&mut mem);
+        assert_eq!(mem[0xC000], 0xAB);
     }
 
     #[test]
     fn test_hli_a() {
         let mut mem = Mmu::init_with_vec(vec![0x22, 0x00, 0x00, 0x00]);
         let mut state: Cpu = Default::default();
-        state.registers.set_hl(0x0003);
+        state.registers.set_hl(0xC000);
         state.registers.a = 0xAB;
 
-        execute(&mut state, &mut mem);
-        assert_eq!(mem[3], 0xAB);
-        assert_eq!(state.registers.get_hl(), 0x0004);
+        state.execute(&mut mem);
+        assert_eq!(mem[0xC000], 0xAB);
+        assert_eq!(state.registers.get_hl(), 0xC001);
     }
 
     #[test]
@@ -1905,7 +2385,7 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.flags.z = false;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         println!("{:?}", state);
         assert_eq!(state.pc, 4);
     }
@@ -1917,7 +2397,7 @@ mod tests {
 
         state.registers.a = 0xAB;
         state.registers.c = 0xAB;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(mem[0xFFAB], 0xAB);
     }
 
@@ -1927,14 +2407,14 @@ mod tests {
         let mut state: Cpu = Default::default();
 
         state.registers.set_bc(0x1000);
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
 
         assert_eq!(state.registers.get_bc(), 0x1001);
 
         let mut state2: Cpu = Default::default();
 
         state2.registers.set_bc(0xFFFF);
-        execute(&mut state2, &mut mem);
+        state2.execute(&mut mem);
         assert_eq!(state2.registers.get_bc(), 0);
     }
 
@@ -1943,25 +2423,532 @@ mod tests {
         let mut mem = Mmu::init_with_vec(vec![0xC3, 0x34, 0x12]);
         let mut state: Cpu = Default::default();
 
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.pc, 0x1234);
     }
 
+    fn bcd(n: u32) -> u8 {
+        (((n / 10) << 4) | (n % 10)) as u8
+    }
+
+    #[test]
+    fn test_daa_after_bcd_arithmetic() {
+        // ADD/ADC/SUB/SBC A, B then DAA, over every pair of BCD operands and
+        // carry in, checked against decimal arithmetic
+        for (opcode, subtract, uses_carry) in [
+            (0x80, false, false),
+            (0x88, false, true),
+            (0x90, true, false),
+            (0x98, true, true),
+        ] {
+            let mut mem = Mmu::init_with_vec(vec![opcode, 0x27]);
+            for x in 0..100 {
+                for y in 0..100 {
+                    for carry_in in [false, true] {
+                        let mut state: Cpu = Default::default();
+                        state.registers.a = bcd(x);
+                        state.registers.b = bcd(y);
+                        state.flags.c = carry_in;
+                        state.execute(&mut mem);
+                        state.execute(&mut mem);
+
+                        let carry_in = uses_carry && carry_in;
+                        let (result, carry) = if subtract {
+                            let difference = x as i32 - y as i32 - carry_in as i32;
+                            (difference.rem_euclid(100) as u32, difference < 0)
+                        } else {
+                            let sum = x + y + carry_in as u32;
+                            (sum % 100, sum >= 100)
+                        };
+                        let inputs = format!("op={opcode:#04x} x={x} y={y} carry={carry_in}");
+                        assert_eq!(state.registers.a, bcd(result), "{inputs}");
+                        assert_eq!(state.flags.c, carry, "{inputs}");
+                        assert_eq!(state.flags.z, result == 0, "{inputs}");
+                        assert_eq!(state.flags.n, subtract, "{inputs}");
+                        assert!(!state.flags.h, "{inputs}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pc_wraps_at_0xffff() {
+        let mut mem = Mmu::init_with_vec(vec![0xC3, 0xFF, 0xFF]);
+        let mut state: Cpu = Default::default();
+
+        mem.set(0xFFFF, 0x3E);
+        mem[0x0000] = 0x42;
+        state.execute(&mut mem);
+        assert_eq!(state.pc, 0xFFFF);
+        state.execute(&mut mem);
+        assert_eq!(state.registers.a, 0x42);
+        assert_eq!(state.pc, 0x0001);
+    }
+
     #[test]
     fn test_inc_b() {
         let mut mem = Mmu::init_with_vec(vec![0x04, 0x00]);
         let mut state: Cpu = Default::default();
 
         state.registers.b = 0x0F;
-        execute(&mut state, &mut mem);
+        state.execute(&mut mem);
         assert_eq!(state.registers.b, 0x10);
         assert!(state.flags.h);
 
         let mut state2: Cpu = Default::default();
         state2.registers.b = 0xFF;
-        execute(&mut state2, &mut mem);
+        state2.execute(&mut mem);
         assert_eq!(state2.registers.b, 0);
         assert!(state2.flags.z);
         assert!(!state2.flags.n);
     }
+
+    #[test]
+    fn test_rst_then_ret_returns_after_rst() {
+        let mut mem = Mmu::init_with_vec(vec![0xEF, 0x00]);
+        mem[0x28] = 0xC9;
+        let mut state: Cpu = Default::default();
+        state.sp = 0xFFFE;
+        state.execute(&mut mem);
+        assert_eq!(state.pc, 0x28);
+        assert_eq!(state.sp, 0xFFFC);
+        assert_eq!(mem.get(0xFFFD), 0x01);
+        assert_eq!(mem.get(0xFFFC), 0x01);
+        state.execute(&mut mem);
+        assert_eq!(state.pc, 0x101);
+        assert_eq!(state.sp, 0xFFFE);
+    }
+
+    // Machine cycles per opcode, with conditional instructions not taken.
+    // 0 marks opcodes that are tested elsewhere or don't exist (STOP, HALT,
+    // the CB prefix and the illegal opcodes).
+    #[rustfmt::skip]
+    const M_CYCLES: [u64; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    // Run `bytes` from 0x0100 on a fresh CPU. The flags are set so that
+    // every conditional jump, call and return is skipped.
+    fn clocks(bytes: &[u8]) -> u64 {
+        let mut mem = Mmu::init_with_vec(bytes.to_vec());
+        let mut state: Cpu = Default::default();
+        let not_taken = match (bytes[0] >> 3) & 0b11 {
+            0 => Flags {
+                z: true,
+                ..state.flags
+            },
+            1 => Flags {
+                z: false,
+                ..state.flags
+            },
+            2 => Flags {
+                c: true,
+                ..state.flags
+            },
+            _ => Flags {
+                c: false,
+                ..state.flags
+            },
+        };
+        state.flags = not_taken;
+        state.execute(&mut mem)
+    }
+
+    #[test]
+    fn test_opcode_timings() {
+        let mut wrong = Vec::new();
+        for (opcode, &m_cycles) in M_CYCLES.iter().enumerate() {
+            if m_cycles == 0 {
+                continue;
+            }
+            let clocks = clocks(&[opcode as u8, 0x00, 0xC0]);
+            if clocks != m_cycles * 4 {
+                wrong.push(format!("{opcode:02X}: {clocks} != {}", m_cycles * 4));
+            }
+        }
+        for opcode in 0..=0xFFu8 {
+            let m_cycles = match opcode {
+                op if op & 0x07 != 6 => 2,
+                0x40..=0x7F => 3,
+                _ => 4,
+            };
+            let clocks = clocks(&[0xCB, opcode]);
+            if clocks != m_cycles * 4 {
+                wrong.push(format!("CB {opcode:02X}: {clocks} != {}", m_cycles * 4));
+            }
+        }
+        assert!(wrong.is_empty(), "Wrong timings:\n{}", wrong.join("\n"));
+    }
+
+    #[test]
+    fn test_taken_branch_timings() {
+        let taken = |bytes: &[u8], z: bool| {
+            let mut mem = Mmu::init_with_vec(bytes.to_vec());
+            let mut state: Cpu = Default::default();
+            state.flags.z = z;
+            state.execute(&mut mem)
+        };
+        // JR NZ, JP NZ, CALL NZ, RET NZ
+        assert_eq!(taken(&[0x20, 0x00], false), 12);
+        assert_eq!(taken(&[0xC2, 0x00, 0xC0], false), 16);
+        assert_eq!(taken(&[0xC4, 0x00, 0xC0], false), 24);
+        assert_eq!(taken(&[0xC0], false), 20);
+    }
+
+    #[test]
+    fn test_set_and_read_registers() {
+        let mut state: Cpu = Default::default();
+        state.set_register(Reg::from_name("A").unwrap(), 0x42);
+        state.set_register(Reg::HL, 0xC123);
+        state.set_register(Reg::AF, 0x12B7);
+        state.set_register(Reg::PC, 0x0150);
+        let registers = state.registers();
+        assert_eq!(registers.get(Reg::A), 0x12);
+        assert_eq!(registers.get(Reg::F), 0xB0);
+        assert_eq!(registers.get(Reg::HL), 0xC123);
+        assert_eq!(registers.get(Reg::H), 0xC1);
+        assert_eq!(registers.pc, 0x0150);
+        assert_eq!(registers.flags(), "Z-HC");
+        assert_eq!(Reg::from_name("ix"), None);
+    }
+
+    #[test]
+    fn test_ld_hlmem_to_vram_dropped_in_mode_3() {
+        use crate::core::registers::STAT;
+
+        // LD (HL), A; LD (HL), 0x42; LD (HL), B
+        let mut mem = Mmu::init_with_vec(vec![0x77, 0x36, 0x42, 0x70]);
+        let mut state: Cpu = Default::default();
+        state.registers.set_hl(0x8010);
+        state.registers.a = 0x12;
+        state.registers.b = 0x34;
+        mem[STAT] = 0x83;
+        for _ in 0..3 {
+            state.execute(&mut mem);
+        }
+        assert_eq!(mem.peek(0x8010), 0x00);
+
+        // The same store goes through once the PPU is in HBlank
+        let mut mem = Mmu::init_with_vec(vec![0x77]);
+        let mut state: Cpu = Default::default();
+        state.registers.set_hl(0x8010);
+        state.registers.a = 0x12;
+        mem[STAT] = 0x80;
+        state.execute(&mut mem);
+        assert_eq!(mem.peek(0x8010), 0x12);
+    }
+
+    #[test]
+    fn test_call_takes_24_clocks() {
+        let mut mem = Mmu::init_with_vec(vec![0xCD, 0x00, 0x02]);
+        let mut state: Cpu = Default::default();
+        assert_eq!(state.execute(&mut mem), 24);
+
+        // CALL NZ taken, then not taken
+        let mut mem = Mmu::init_with_vec(vec![0xC4, 0x00, 0x02]);
+        let mut state: Cpu = Default::default();
+        state.flags.z = false;
+        assert_eq!(state.execute(&mut mem), 24);
+        let mut mem = Mmu::init_with_vec(vec![0xC4, 0x00, 0x02]);
+        let mut state: Cpu = Default::default();
+        state.flags.z = true;
+        assert_eq!(state.execute(&mut mem), 12);
+    }
+
+    #[test]
+    fn test_call_then_ret_returns_after_call() {
+        let mut mem = Mmu::init_with_vec(vec![0xCD, 0x00, 0x02]);
+        mem[0x200] = 0xC9;
+        let mut state: Cpu = Default::default();
+        state.sp = 0xFFFE;
+        state.execute(&mut mem);
+        assert_eq!(state.pc, 0x200);
+        assert_eq!(mem.get(0xFFFD), 0x01);
+        assert_eq!(mem.get(0xFFFC), 0x03);
+        state.execute(&mut mem);
+        assert_eq!(state.pc, 0x103);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_servicing_when_ime_off() {
+        let mut mem = Mmu::init_with_vec(vec![0x76, 0x00]);
+        let mut state: Cpu = Default::default();
+        state.ime = false;
+        state.execute(&mut mem);
+        assert!(state.halted);
+        let pc = state.pc;
+
+        // Requested but not enabled in IE: keep sleeping
+        mem.set(0xFFFF, 0x00);
+        mem.set(0xFF0F, 0x04);
+        state.handle_interrupts(&mut mem);
+        assert!(state.halted);
+
+        mem.set(0xFFFF, 0x04);
+        state.handle_interrupts(&mut mem);
+        assert!(!state.halted);
+        assert_eq!(state.pc, pc);
+        assert_eq!(mem.get(0xFF0F) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_halt_wakes_and_services_when_ime_on() {
+        let mut mem = Mmu::init_with_vec(vec![0x76, 0x00]);
+        let mut state: Cpu = Default::default();
+        state.ime = true;
+        state.execute(&mut mem);
+        mem.set(0xFFFF, 0x04);
+        mem.set(0xFF0F, 0x04);
+        state.handle_interrupts(&mut mem);
+        assert!(!state.halted);
+        assert_eq!(state.pc, 0x50);
+        assert_eq!(mem.get(0xFF0F) & 0x04, 0);
+    }
+
+    #[test]
+    fn test_cb_rotates_set_z_but_a_rotates_clear_it() {
+        // RLC B; RL C
+        let mut mem = Mmu::init_with_vec(vec![0xCB, 0x00, 0xCB, 0x11]);
+        let mut state: Cpu = Default::default();
+        state.registers.b = 0x00;
+        state.registers.c = 0x80;
+        state.flags.z = false;
+        state.execute(&mut mem);
+        assert_eq!(state.registers.b, 0x00);
+        assert!(state.flags.z);
+        assert!(!state.flags.c);
+        state.execute(&mut mem);
+        assert_eq!(state.registers.c, 0x00);
+        assert!(state.flags.z);
+        assert!(state.flags.c);
+
+        // RLCA; RLA
+        let mut mem = Mmu::init_with_vec(vec![0x07, 0x17]);
+        let mut state: Cpu = Default::default();
+        state.registers.a = 0x00;
+        state.flags.z = true;
+        state.execute(&mut mem);
+        assert_eq!(state.registers.a, 0x00);
+        assert!(!state.flags.z);
+        assert!(!state.flags.c);
+        state.registers.a = 0x80;
+        state.flags.z = true;
+        state.execute(&mut mem);
+        assert_eq!(state.registers.a, 0x00);
+        assert!(!state.flags.z);
+        assert!(state.flags.c);
+    }
+
+    #[test]
+    fn test_sp_wraps_around_on_push_and_pop() {
+        // PUSH BC; POP DE
+        let mut mem = Mmu::init_with_vec(vec![0xC5, 0xD1]);
+        let mut state: Cpu = Default::default();
+        state.sp = 0x0000;
+        state.registers.set_bc(0x1234);
+        state.execute(&mut mem);
+        assert_eq!(state.sp, 0xFFFE);
+        assert_eq!((mem.get(0xFFFF), mem.get(0xFFFE)), (0x12, 0x34));
+        state.execute(&mut mem);
+        assert_eq!(state.sp, 0x0000);
+        assert_eq!(state.registers.get_de(), 0x1234);
+
+        // RET pops the last byte of memory and wraps back to 0x0000
+        let mut rom = vec![0u8; 0x200];
+        rom[..3].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        rom[0x100] = 0xC9;
+        let mut mem = Mmu::init_with_vec(rom);
+        let mut state: Cpu = Default::default();
+        state.sp = 0x0000;
+        state.execute(&mut mem);
+        assert_eq!((state.pc, state.sp), (0x200, 0xFFFE));
+        state.execute(&mut mem);
+        assert_eq!((state.pc, state.sp), (0x103, 0x0000));
+
+        // Servicing an interrupt pushes the same way
+        state.sp = 0x0000;
+        state.ime = true;
+        mem.set(0xFFFF, 0x04);
+        mem.set(0xFF0F, 0x04);
+        state.handle_interrupts(&mut mem);
+        assert_eq!((state.pc, state.sp), (0x50, 0xFFFE));
+        assert_eq!(mem.get(0xFFFE), 0x03);
+    }
+
+    #[test]
+    fn test_opcode_breakpoint() {
+        // NOP; HALT
+        let mut mem = Mmu::init_with_vec(vec![0x00, 0x76]);
+        let mut state: Cpu = Default::default();
+        state.add_opcode_breakpoint(0x76);
+        state.execute(&mut mem);
+        assert_eq!(state.take_break(), None);
+        state.execute(&mut mem);
+        let cause = state.take_break().unwrap();
+        assert_eq!(
+            cause,
+            BreakCause::Opcode {
+                opcode: 0x76,
+                pc: 0x101
+            }
+        );
+        assert_eq!(cause.to_string(), "Break: opcode 76 ran at 0x0101");
+        assert_eq!(state.take_break(), None);
+    }
+
+    #[test]
+    fn test_reset_keeps_profile_and_breakpoints() {
+        let mut mem = Mmu::init_with_vec(vec![0x00]);
+        let mut state: Cpu = Default::default();
+        state.enable_profiling();
+        state.add_opcode_breakpoint(0x00);
+        state.execute(&mut mem);
+        assert!(state.take_break().is_some());
+
+        state.registers.a = 0x42;
+        state.reset();
+        assert_eq!((state.pc, state.registers.a), (0x100, 0x01));
+        state.execute(&mut mem);
+        assert!(state.take_break().is_some());
+        assert_eq!(state.profile_report(), [(0x00, 2)]);
+    }
+
+    #[test]
+    fn test_run_to_stops_once() {
+        // INC B; JR -3
+        let mut mem = Mmu::init_with_vec(vec![0x04, 0x18, 0xFD]);
+        let mut state: Cpu = Default::default();
+        state.run_to(Location::new(0x100));
+        state.execute(&mut mem);
+        assert_eq!(state.take_break(), None);
+        state.execute(&mut mem);
+        let cause = state.take_break().unwrap();
+        assert_eq!(cause, BreakCause::Address(0x100));
+        assert_eq!(cause.to_string(), "Break: reached 0x0100");
+        for _ in 0..10 {
+            state.execute(&mut mem);
+            assert_eq!(state.take_break(), None);
+        }
+    }
+
+    #[test]
+    fn test_interrupt_breakpoint() {
+        let mut mem = Mmu::init_with_vec(vec![0x00]);
+        let mut state: Cpu = Default::default();
+        state.ime = true;
+        state.add_interrupt_breakpoint(Interrupt::parse("timer").unwrap());
+        mem.set(0xFFFF, 0x05);
+        mem.set(0xFF0F, 0x01);
+        state.handle_interrupts(&mut mem);
+        assert_eq!(state.pc, 0x40);
+        assert_eq!(state.take_break(), None);
+
+        state.ime = true;
+        mem.set(0xFF0F, 0x04);
+        state.handle_interrupts(&mut mem);
+        assert_eq!(state.pc, 0x50);
+        assert_eq!(
+            state.take_break(),
+            Some(BreakCause::Interrupt(Interrupt::Timer))
+        );
+        assert!(Interrupt::parse("hblank").is_err());
+    }
+
+    #[test]
+    fn test_run_to_stops_at_interrupt_vector() {
+        let mut mem = Mmu::init_with_vec(vec![0x00]);
+        let mut state: Cpu = Default::default();
+        state.ime = true;
+        state.run_to(Location::new(0x40));
+        mem.set(0xFFFF, 0x01);
+        mem.set(0xFF0F, 0x01);
+        state.handle_interrupts(&mut mem);
+        assert_eq!(state.pc, 0x40);
+        assert_eq!(state.take_break(), Some(BreakCause::Address(0x40)));
+    }
+
+    #[test]
+    fn test_add_sp_e8_flags() {
+        let mut mem = Mmu::init_with_vec(vec![0xE8, 0x01]);
+        let mut state: Cpu = Default::default();
+        state.sp = 0x000F;
+        state.execute(&mut mem);
+        assert_eq!(state.sp, 0x0010);
+        assert!(state.flags.h);
+        assert!(!state.flags.c);
+
+        // -1 is 0xFF as a byte, so any nonzero low nibble and byte carry
+        let mut mem = Mmu::init_with_vec(vec![0xE8, 0xFF]);
+        let mut state: Cpu = Default::default();
+        state.sp = 0x0001;
+        state.execute(&mut mem);
+        assert_eq!(state.sp, 0x0000);
+        assert!(state.flags.h);
+        assert!(state.flags.c);
+        assert!(!state.flags.z);
+
+        let mut mem = Mmu::init_with_vec(vec![0xE8, 0xFF]);
+        let mut state: Cpu = Default::default();
+        state.sp = 0x0100;
+        state.execute(&mut mem);
+        assert_eq!(state.sp, 0x00FF);
+        assert!(!state.flags.h);
+        assert!(!state.flags.c);
+    }
+
+    #[test]
+    fn test_ld_hl_sp_e8_flags() {
+        let mut mem = Mmu::init_with_vec(vec![0xF8, 0x80]);
+        let mut state: Cpu = Default::default();
+        state.sp = 0xFFF8;
+        state.execute(&mut mem);
+        assert_eq!(state.registers.get_hl(), 0xFF78);
+        assert_eq!(state.sp, 0xFFF8);
+        assert!(!state.flags.h);
+        assert!(state.flags.c);
+    }
+
+    #[test]
+    fn test_inc_hlmem_half_carry() {
+        let mut mem = Mmu::init_with_vec(vec![0x34]);
+        let mut state: Cpu = Default::default();
+
+        state.registers.set_hl(0xC000);
+        mem.set(0xC000, 0x0F);
+        assert_eq!(state.execute(&mut mem), 12);
+        assert_eq!(mem.get(0xC000), 0x10);
+        assert!(state.flags.h);
+        assert!(!state.flags.z);
+        assert!(!state.flags.n);
+    }
+
+    #[test]
+    fn test_dec_hlmem_half_carry() {
+        let mut mem = Mmu::init_with_vec(vec![0x35]);
+        let mut state: Cpu = Default::default();
+
+        state.registers.set_hl(0xC000);
+        mem.set(0xC000, 0x10);
+        assert_eq!(state.execute(&mut mem), 12);
+        assert_eq!(mem.get(0xC000), 0x0F);
+        assert!(state.flags.h);
+        assert!(!state.flags.z);
+        assert!(state.flags.n);
+    }
 }
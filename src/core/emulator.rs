@@ -0,0 +1,1613 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "apng")]
+use crate::core::apng::ApngRecorder;
+use crate::core::apu::APU;
+use crate::core::cheats::CheatError;
+use crate::core::cpu::{BreakCause, Cpu, Reg, RegisterSnapshot};
+use crate::core::frontend::{
+    AudioSink, AudioView, DebugView, Event, EventQueue, NoEvents, NullRenderer, Renderer,
+};
+use crate::core::input::Button;
+use crate::core::interrupts::Interrupt;
+use crate::core::mmu::{Mmu, RomError};
+use crate::core::ppu::{Colors, PPU};
+use crate::core::registers::{LY, NR52};
+use crate::core::replay::{Replay, ReplayInput};
+use crate::core::serial::{Serial, SerialPeer};
+use crate::core::symbols::{mapped_bank, Location, Symbols};
+use crate::disassembler::jump_target;
+
+// Used when there's no audio sink to take the rate from
+const DEFAULT_SAMPLE_RATE: i32 = 44100;
+const DIV_RATE: u64 = 16384;
+const FRAME_DURATION: u64 = 16_743;
+// Range and step of the speed multiplier
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 4.0;
+const SPEED_STEP: f64 = 0.25;
+// Instructions the debugger can undo
+const UNDO_DEPTH: usize = 1024;
+// How long a watched ROM has to stay unchanged before it's reloaded, so a
+// build that's still writing it isn't loaded half done
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// The CPU before an instruction and the memory it changed
+struct UndoStep {
+    cpu: Cpu,
+    writes: Vec<(usize, u8)>,
+}
+
+// Where `--trace` writes each instruction, and the PC to start at
+struct Trace<'a> {
+    out: Box<dyn Write + 'a>,
+    start: Option<u16>,
+}
+
+// Where battery-backed RAM is written, and how often
+struct SaveFile {
+    path: PathBuf,
+    // Zero saves only on exit
+    interval: Duration,
+    last_save: Instant,
+}
+
+// A ROM file to reload when it changes
+struct RomWatch {
+    path: PathBuf,
+    // Modification time of the ROM that's running
+    loaded: Option<SystemTime>,
+    // A newer modification time, and when it was first seen
+    pending: Option<(SystemTime, Instant)>,
+}
+
+pub struct Emulator<'a> {
+    cpu: Cpu,
+    ppu: PPU,
+    renderer: Box<dyn Renderer + 'a>,
+    mmu: Mmu,
+    apu: APU,
+    audio: Option<Box<dyn AudioSink + 'a>>,
+    serial: Serial,
+    serial_peer: Option<Box<dyn SerialPeer + 'a>>,
+    event_queue: Box<dyn EventQueue + 'a>,
+    debug_views: Vec<Box<dyn DebugView + 'a>>,
+    audio_views: Vec<Box<dyn AudioView + 'a>>,
+    timer_cycle_count: u64,
+    paused: bool,
+    // Frames rendered so far
+    frame: u64,
+    replay: Option<Replay>,
+    recording: Option<Replay>,
+    history: VecDeque<UndoStep>,
+    // 2.0 runs twice as fast as the hardware
+    speed: f64,
+    trace: Option<Trace<'a>>,
+    save_file: Option<SaveFile>,
+    rom_watch: Option<RomWatch>,
+    symbols: Symbols,
+    // Addresses the debugger stops at every time
+    address_breakpoints: Vec<Location>,
+    #[cfg(feature = "apng")]
+    apng: Option<ApngRecorder>,
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(
+        renderer: Box<dyn Renderer + 'a>,
+        event_queue: Box<dyn EventQueue + 'a>,
+        mmu: Mmu,
+        audio: Option<Box<dyn AudioSink + 'a>>,
+    ) -> Result<Self, String> {
+        let sample_rate = audio
+            .as_ref()
+            .map_or(DEFAULT_SAMPLE_RATE, |audio| audio.sample_rate());
+        let cpu = if mmu.boot_rom_mapped() {
+            Cpu::boot()
+        } else {
+            Cpu::default()
+        };
+        Ok(Self {
+            cpu,
+            ppu: PPU::new(),
+            renderer,
+            mmu,
+            apu: APU::new(sample_rate),
+            audio,
+            serial: Serial::default(),
+            serial_peer: None,
+            event_queue,
+            debug_views: Vec::new(),
+            audio_views: Vec::new(),
+            timer_cycle_count: 0,
+            paused: false,
+            frame: 0,
+            replay: None,
+            recording: None,
+            history: VecDeque::new(),
+            speed: 1.0,
+            trace: None,
+            save_file: None,
+            rom_watch: None,
+            symbols: Symbols::default(),
+            address_breakpoints: Vec::new(),
+            #[cfg(feature = "apng")]
+            apng: None,
+        })
+    }
+
+    // No screen, input or sound, for tests and tools that run a ROM
+    // unattended
+    pub fn headless(mmu: Mmu) -> Result<Self, String> {
+        Self::new(Box::new(NullRenderer), Box::new(NoEvents), mmu, None)
+    }
+
+    // Power-cycle the Game Boy, keeping the cartridge and its saved RAM.
+    // The CPU starts at 0x0100 with the registers the boot ROM leaves.
+    pub fn reset(&mut self) {
+        // The reset forgets which RAM is unsaved, so save it first
+        if let Err(err) = self.write_save() {
+            eprintln!("{err}");
+        }
+        self.mmu.reset();
+        self.cpu.reset();
+        self.ppu.reset();
+        self.apu = APU::new(self.apu_sample_rate());
+        if !self.audio_views.is_empty() {
+            self.apu.enable_history();
+        }
+        self.serial = Serial::default();
+        self.timer_cycle_count = 0;
+        self.frame = 0;
+        self.history.clear();
+        // Replays count frames from power-on, so a replay starts over and a
+        // recording keeps only what comes after the reset
+        if let Some(replay) = &mut self.replay {
+            replay.rewind();
+        }
+        if self.recording.is_some() {
+            self.recording = Some(Replay::default());
+        }
+        self.apply_replay();
+    }
+
+    // Swap in another cartridge and reset. On error the current one keeps
+    // running. The save file belonged to the old cartridge, so it's written
+    // one last time and dropped.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        let mut mmu = Mmu::init_with_ram(self.mmu.init_ram());
+        mmu.initialize_from_bytes(rom)?;
+        if let Err(err) = self.write_save() {
+            eprintln!("{err}");
+        }
+        self.save_file = None;
+        // The cheats and settings carry over to the new cartridge, and
+        // `Mmu::reset` keeps them from there
+        mmu.cheats = std::mem::take(&mut self.mmu.cheats);
+        mmu.set_stat_write_bug(self.mmu.stat_write_bug());
+        mmu.input
+            .set_block_opposite_directions(self.mmu.input.blocks_opposite_directions());
+        self.mmu = mmu;
+        self.reset();
+        Ok(())
+    }
+
+    // Scale frame pacing, DIV and audio by `speed`, clamped to 0.25-4.0.
+    // Sound is generated for the scaled time, so it plays back faster and
+    // higher pitched, or slower and lower.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.apu.set_sample_rate(self.apu_sample_rate());
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    fn apu_sample_rate(&self) -> i32 {
+        let sample_rate = self
+            .audio
+            .as_ref()
+            .map_or(DEFAULT_SAMPLE_RATE, |audio| audio.sample_rate());
+        (sample_rate as f64 / self.speed) as i32
+    }
+
+    fn frame_duration(&self) -> Duration {
+        Duration::from_micros(FRAME_DURATION).div_f64(self.speed)
+    }
+
+    // Write a line per instruction to `out`, in the debugger's format. With
+    // `start`, logging begins the first time PC reaches it.
+    pub fn set_trace(&mut self, out: Box<dyn Write + 'a>, start: Option<u16>) {
+        self.trace = Some(Trace { out, start });
+    }
+
+    fn write_trace(&mut self) -> Result<(), String> {
+        let Some(trace) = &mut self.trace else {
+            return Ok(());
+        };
+        if trace.start.is_some_and(|start| start != self.cpu.pc()) {
+            return Ok(());
+        }
+        trace.start = None;
+        writeln!(trace.out, "{}", self.cpu.state_line(&self.mmu))
+            .map_err(|e| format!("Error writing trace: {e}"))
+    }
+
+    // Plug in the other end of the link cable
+    pub fn set_serial_peer(&mut self, peer: Box<dyn SerialPeer + 'a>) {
+        self.serial_peer = Some(peer);
+    }
+
+    // Redraw `view` after every frame `run` renders
+    pub fn add_debug_view(&mut self, view: Box<dyn DebugView + 'a>) {
+        self.debug_views.push(view);
+    }
+
+    // The APU only keeps its channels' history once there's a view for it
+    pub fn add_audio_view(&mut self, view: Box<dyn AudioView + 'a>) {
+        self.apu.enable_history();
+        self.audio_views.push(view);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmu.set_button(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmu.set_button(button, false);
+    }
+
+    // Play back `replay`'s inputs, each at the start of its frame, counting
+    // from the current one. Live button events are ignored from then on.
+    pub fn set_replay(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+        self.frame = 0;
+        self.apply_replay();
+    }
+
+    // Record every live button change from here on, with frames counted
+    // from the current one
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Replay::default());
+        self.frame = 0;
+    }
+
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
+    }
+
+    fn apply_replay(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            for input in replay.take_due(self.frame) {
+                self.mmu.set_button(input.button, input.pressed);
+            }
+        }
+    }
+
+    pub fn set_palette(&mut self, colors: Colors) {
+        self.ppu.set_palette(colors);
+    }
+
+    // Only draw one frame in every `frame_skip + 1`. Emulation runs exactly
+    // as before; the skipped frames just aren't drawn or shown.
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.ppu.set_frame_skip(frame_skip);
+    }
+
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        self.mmu.cheats.add(code)
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.cpu.enable_profiling();
+    }
+
+    pub fn print_profile(&self) {
+        let report = self.cpu.profile_report();
+        let instructions: u64 = report.iter().map(|(_, count)| count).sum();
+        println!(
+            "Executed {} instructions in {} cycles",
+            instructions,
+            self.cpu.profiled_cycles()
+        );
+        for (opcode, count) in report {
+            println!("{:02X}: {}", opcode, count);
+        }
+        for (opcode, count) in self.cpu.cb_profile_report() {
+            println!("CB {:02X}: {}", opcode, count);
+        }
+    }
+
+    // Run one instruction (or 4 idle cycles while halted or stopped) and
+    // bring the APU, PPU and timer up to date. Returns true if the PPU
+    // finished a frame.
+    //
+    // Interrupts are checked before IME is turned on by a preceding EI, so
+    // the instruction after EI always runs first. RETI sets IME directly, so
+    // an interrupt pending when it returns is serviced on the next step.
+    fn step(&mut self) -> Result<bool, String> {
+        self.cpu.handle_interrupts(&mut self.mmu);
+        self.cpu.enable_ime_delayed();
+        self.cpu.resume_from_stop(&self.mmu);
+
+        let cycles = if !self.cpu.halted && !self.cpu.stopped {
+            self.write_trace()?;
+            self.cpu.execute(&mut self.mmu)
+        } else {
+            4
+        };
+
+        // In double speed mode the PPU and APU see half as many cycles
+        let device_cycles = if self.cpu.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        };
+
+        let audio = self
+            .audio
+            .as_mut()
+            .map(|audio| audio.as_mut() as &mut dyn AudioSink);
+        self.apu.update(device_cycles as u32, &mut self.mmu, audio);
+        let peer = self
+            .serial_peer
+            .as_mut()
+            .map(|peer| peer.as_mut() as &mut dyn SerialPeer);
+        self.serial.update(cycles as u32, &mut self.mmu, peer);
+        self.mmu.update_dma(cycles as u32);
+
+        let new_frame =
+            self.ppu
+                .render(&mut self.mmu, device_cycles as i32, self.renderer.as_mut())?;
+        self.update_timer(cycles);
+        if new_frame {
+            self.mmu.apply_ram_cheats();
+            self.frame += 1;
+            self.apply_replay();
+            #[cfg(feature = "apng")]
+            if let Some(apng) = &mut self.apng {
+                apng.add_frame(self.ppu.framebuffer());
+            }
+        }
+        Ok(new_frame)
+    }
+
+    // Step, remembering enough to undo it
+    fn step_with_undo(&mut self) -> Result<bool, String> {
+        let cpu = self.cpu;
+        self.mmu.start_journal();
+        let new_frame = self.step();
+        let writes = self.mmu.take_journal();
+        if self.history.len() == UNDO_DEPTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(UndoStep { cpu, writes });
+        new_frame
+    }
+
+    // Roll back the last instruction run by the debugger. The PPU, APU and
+    // timer keep their state. Returns false if there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(step) = self.history.pop_back() else {
+            return false;
+        };
+        self.mmu.undo_writes(&step.writes);
+        self.cpu.rewind_to(step.cpu);
+        true
+    }
+
+    // The address a CALL or RST at PC returns to, or None for any other
+    // instruction
+    fn call_return_address(&self) -> Option<u16> {
+        let pc = self.cpu.pc();
+        match self.mmu.get(pc as usize) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => Some(pc.wrapping_add(3)),
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some(pc.wrapping_add(1)),
+            _ => None,
+        }
+    }
+
+    // Run the instruction at PC, and if it's a CALL or RST, keep going until
+    // it returns. The return is caught by PC coming back to the next
+    // instruction with SP back where it was, so a recursive call to the same
+    // site doesn't stop early. If the routine unwinds its frame some other
+    // way, SP goes above where it started and we stop there.
+    pub fn step_over(&mut self) -> Result<(), String> {
+        let Some(return_address) = self.call_return_address() else {
+            self.step_with_undo()?;
+            self.report_break();
+            return Ok(());
+        };
+        let sp = self.cpu.registers().sp;
+        loop {
+            self.step_with_undo()?;
+            if self.report_break() {
+                return Ok(());
+            }
+            let registers = self.cpu.registers();
+            if (registers.pc == return_address && registers.sp >= sp) || registers.sp > sp {
+                return Ok(());
+            }
+        }
+    }
+
+    // Print why the debugger stopped, if the last step hit a breakpoint
+    fn report_break(&mut self) -> bool {
+        let pc = self.cpu.pc();
+        let cause = self.cpu.take_break().or_else(|| {
+            self.address_breakpoints
+                .iter()
+                .any(|location| location.contains(&self.mmu, pc))
+                .then_some(BreakCause::Address(pc))
+        });
+        let Some(cause) = cause else {
+            return false;
+        };
+        let address = match cause {
+            BreakCause::Opcode { pc, .. } => Some(pc),
+            BreakCause::Address(address) => Some(address),
+            BreakCause::Interrupt(_) => None,
+        };
+        match address.and_then(|address| self.describe_address(address)) {
+            Some(location) => println!("{cause} ({location})"),
+            None => println!("{cause}"),
+        }
+        true
+    }
+
+    // Label the debugger with a .sym file's symbols
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    // The nearest symbol to `address`, plus an offset, in whatever bank is
+    // mapped there
+    fn describe_address(&self, address: u16) -> Option<String> {
+        self.symbols
+            .describe(mapped_bank(&self.mmu, address), address)
+    }
+
+    // A symbol name or a hex address, for debugger commands. A symbol only
+    // matches in its own bank, and an address in any.
+    fn parse_location(&self, text: &str) -> Result<Location, String> {
+        if let Some(location) = self.symbols.location_of(text) {
+            return Ok(location);
+        }
+        let address = parse_hex(text)?;
+        u16::try_from(address)
+            .map(Location::new)
+            .map_err(|_| format!("Invalid address {text}"))
+    }
+
+    // The CPU state for the debugger's prompt, with the symbols for PC and
+    // any jump target
+    fn log_state(&self) {
+        let line = self.cpu.state_line(&self.mmu);
+        if self.symbols.is_empty() {
+            println!("{line}");
+            return;
+        }
+        let pc = self.cpu.pc();
+        let bytes = [0, 1, 2].map(|i| self.mmu.peek(pc.wrapping_add(i) as usize));
+        let location = self.describe_address(pc).unwrap_or_default();
+        match jump_target(&bytes, pc).and_then(|target| self.describe_address(target)) {
+            Some(target) => println!("{location}: {line} -> {target}"),
+            None => println!("{location}: {line}"),
+        }
+    }
+
+    // `<hex opcode>`, for the debugger's `break-op` command
+    fn break_op_command(&mut self, args: &str) -> Result<(), String> {
+        let opcode = parse_hex(args)?;
+        let opcode = u8::try_from(opcode).map_err(|_| format!("Opcode {args} is too big"))?;
+        self.cpu.add_opcode_breakpoint(opcode);
+        Ok(())
+    }
+
+    // `<register> <hex value>`, for the debugger's `set` command
+    fn set_register_command(&mut self, args: &str) -> Result<(), String> {
+        let [name, value] = args.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err("Usage: set <register> <hex value>".to_string());
+        };
+        let reg = Reg::from_name(name).ok_or(format!("Unknown register {name}"))?;
+        let value = u16::try_from(parse_hex(value)?).map_err(|_| format!("{value} is too big"))?;
+        self.cpu.set_register(reg, value);
+        Ok(())
+    }
+
+    // `<start> <end> <file>`, for the debugger's `dump` command. The end is
+    // exclusive, so `dump 8000 a000 vram.bin` saves all of VRAM.
+    fn dump_command(&self, args: &str) -> Result<(), String> {
+        let [start, end, path] = args.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err("Usage: dump <start> <end> <file>".to_string());
+        };
+        let bytes = self.mmu.dump_range(parse_hex(start)?, parse_hex(end)?);
+        std::fs::write(path, &bytes).map_err(|e| format!("Error writing {path}: {e}"))?;
+        println!("Wrote {} bytes to {path}", bytes.len());
+        Ok(())
+    }
+
+    // `<file> <start>`, for the debugger's `load` command
+    fn load_command(&mut self, args: &str) -> Result<(), String> {
+        let [path, start] = args.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err("Usage: load <file> <start>".to_string());
+        };
+        let bytes = std::fs::read(path).map_err(|e| format!("Error reading {path}: {e}"))?;
+        self.mmu.load_range(parse_hex(start)?, &bytes)
+    }
+
+    fn update_timer(&mut self, cycles: u64) {
+        let tac = self.mmu.get(0xFF07);
+        let timer_enable = (tac & 0b100) >> 2 != 0;
+        let timer_cycles = match tac & 0b11 {
+            0b00 => 1024,
+            0b01 => 16,
+            0b10 => 64,
+            0b11 => 256,
+            _ => unreachable!(),
+        };
+
+        if timer_enable {
+            self.timer_cycle_count += cycles;
+            while self.timer_cycle_count >= timer_cycles {
+                self.timer_cycle_count -= timer_cycles;
+                let mut tima = self.mmu.get(0xFF05);
+                tima = tima.wrapping_add(1);
+                if tima == 0 {
+                    self.mmu.set(0xFF05, self.mmu.get(0xFF06));
+                    Interrupt::Timer.trigger(&mut self.mmu);
+                } else {
+                    self.mmu.set(0xFF05, tima);
+                }
+            }
+        }
+    }
+
+    // Run as fast as possible until `frames` frames have been rendered, with
+    // no input, debug windows or frame pacing. DIV follows wall-clock time in
+    // `run`, so it doesn't advance here; this keeps headless runs repeatable.
+    pub fn run_frames(&mut self, frames: usize) -> Result<(), String> {
+        let mut rendered = 0;
+        while rendered < frames {
+            if self.step()? {
+                rendered += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Write battery-backed RAM to `path` on exit, and every `interval` while
+    // running if it has changed. A zero interval only saves on exit.
+    // Cartridges without a battery never write the file.
+    pub fn set_save_file(&mut self, path: impl Into<PathBuf>, interval: Duration) {
+        self.save_file = Some(SaveFile {
+            path: path.into(),
+            interval,
+            last_save: Instant::now(),
+        });
+    }
+
+    // Reload the ROM at `path` whenever the file changes, for homebrew
+    // development. The battery RAM is written to the save file first and
+    // read back into the new cartridge.
+    pub fn watch_rom(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        self.rom_watch = Some(RomWatch {
+            loaded: modified_time(&path),
+            path,
+            pending: None,
+        });
+    }
+
+    // Reload the watched ROM once a change has settled. Called between
+    // frames. A ROM that fails to load is reported and the old one keeps
+    // running.
+    fn check_rom_watch(&mut self) {
+        let Some(watch) = &mut self.rom_watch else {
+            return;
+        };
+        // Missing while the build replaces it
+        let Some(modified) = modified_time(&watch.path) else {
+            return;
+        };
+        if Some(modified) == watch.loaded {
+            watch.pending = None;
+            return;
+        }
+        match watch.pending {
+            Some((pending, since)) if pending == modified => {
+                if since.elapsed() < WATCH_DEBOUNCE {
+                    return;
+                }
+            }
+            _ => {
+                watch.pending = Some((modified, Instant::now()));
+                return;
+            }
+        }
+        watch.loaded = Some(modified);
+        watch.pending = None;
+        let path = watch.path.clone();
+        match std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|rom| self.reload_rom(&rom))
+        {
+            Ok(()) => println!("Reloaded {}", path.display()),
+            Err(err) => eprintln!("Error reloading {}: {err}", path.display()),
+        }
+    }
+
+    // Like `load_rom`, but the same game, so it keeps the save file. Only
+    // fails if the new ROM can't be loaded, leaving the old one running.
+    fn reload_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        let save_file = self
+            .save_file
+            .as_ref()
+            .map(|save_file| (save_file.path.clone(), save_file.interval));
+        self.load_rom(rom).map_err(|e| e.to_string())?;
+        if let Some((path, interval)) = save_file {
+            // If the rebuild changed the RAM size the old save doesn't fit.
+            // The new game starts with blank RAM and saves over it.
+            if let Ok(data) = std::fs::read(&path) {
+                if let Err(err) = self.mmu.load_save_data(&data) {
+                    eprintln!("Not loading {}: {err}", path.display());
+                }
+            }
+            self.set_save_file(path, interval);
+        }
+        Ok(())
+    }
+
+    // Save if the interval is up. Called between frames, so the file never
+    // holds half of a frame's writes.
+    fn autosave(&mut self) -> Result<(), String> {
+        match &self.save_file {
+            Some(save_file)
+                if !save_file.interval.is_zero()
+                    && save_file.last_save.elapsed() >= save_file.interval =>
+            {
+                self.write_save()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Write battery-backed RAM to the save file if it changed since the last
+    // write
+    fn write_save(&mut self) -> Result<(), String> {
+        let Some(save_file) = &mut self.save_file else {
+            return Ok(());
+        };
+        save_file.last_save = Instant::now();
+        if !self.mmu.take_ram_dirty() {
+            return Ok(());
+        }
+        let Some(data) = self.mmu.save_data() else {
+            return Ok(());
+        };
+        std::fs::write(&save_file.path, data)
+            .map_err(|e| format!("Error writing {}: {e}", save_file.path.display()))
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.ppu.framebuffer()
+    }
+
+    // Record one frame in two from now on to an animated PNG at `path`,
+    // replacing any recording already going. With `--frame-skip`, a skipped
+    // frame repeats the last drawn one, so it just shows that one for longer.
+    #[cfg(feature = "apng")]
+    pub fn start_apng(&mut self, path: impl Into<std::path::PathBuf>) -> Result<(), String> {
+        self.stop_apng()?;
+        self.apng = Some(ApngRecorder::new(path));
+        Ok(())
+    }
+
+    // Write out the recording, if there is one
+    #[cfg(feature = "apng")]
+    pub fn stop_apng(&mut self) -> Result<(), String> {
+        match self.apng.take() {
+            Some(apng) => apng.finish(),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "apng")]
+    fn toggle_apng(&mut self) -> Result<(), String> {
+        if let Some(apng) = &self.apng {
+            println!("Saved recording to {}", apng.path().display());
+            return self.stop_apng();
+        }
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = format!("gumball-{seconds}.png");
+        println!("Recording to {path}");
+        self.start_apng(path)
+    }
+
+    #[cfg(not(feature = "apng"))]
+    fn toggle_apng(&mut self) -> Result<(), String> {
+        println!("Recording needs the apng feature: cargo run --features apng");
+        Ok(())
+    }
+
+    // The CPU registers, for checking a test ROM's result after a headless
+    // run. Blargg's tests signal a pass with B-L set to 3, 5, 8, 13, 21, 34.
+    pub fn cpu_snapshot(&self) -> RegisterSnapshot {
+        self.cpu.registers()
+    }
+
+    // A snapshot of the machine for external tools to poll, as a JSON object.
+    // Only reads registers, so it's cheap enough to call every frame.
+    pub fn status_json(&self) -> String {
+        let registers = self.cpu.registers();
+        let nr52 = self.mmu.get(NR52);
+        serde_json::json!({
+            "frame": self.frame,
+            "paused": self.paused,
+            "cpu": {
+                "a": registers.a,
+                "f": registers.f,
+                "b": registers.b,
+                "c": registers.c,
+                "d": registers.d,
+                "e": registers.e,
+                "h": registers.h,
+                "l": registers.l,
+                "sp": registers.sp,
+                "pc": registers.pc,
+                "ime": self.cpu.ime(),
+                "halted": self.cpu.halted,
+            },
+            "ppu": {
+                "mode": self.mmu.ppu_mode(),
+                "ly": self.mmu.get(LY),
+            },
+            // Whether each channel is playing (NR52) and whether it's been
+            // muted for debugging
+            "apu": {
+                "enabled": nr52 & 0x80 != 0,
+                "channels": (1..=4).map(|channel| serde_json::json!({
+                    "on": nr52 & (1 << (channel - 1)) != 0,
+                    "muted": !self.apu.channel_enabled(channel),
+                })).collect::<Vec<_>>(),
+            },
+            "banks": {
+                "rom": self.mmu.current_rom_bank(),
+                "ram": self.mmu.current_ram_bank(),
+            },
+            "interrupts": {
+                "ie": self.mmu.get(0xFFFF),
+                "if": self.mmu.get(0xFF0F),
+            },
+        })
+        .to_string()
+    }
+
+    // Handle pending input events. Returns false once the user asks to quit.
+    fn handle_events(&mut self) -> Result<bool, String> {
+        while let Some(event) = self.event_queue.poll_event() {
+            match event {
+                Event::Quit => return Ok(false),
+                Event::TogglePause => self.paused = !self.paused,
+                Event::Step if self.paused => {
+                    self.step()?;
+                    self.log_state();
+                }
+                Event::Step => {}
+                Event::ToggleChannel(channel) => self.apu.toggle_channel(channel),
+                Event::ToggleApng => self.toggle_apng()?,
+                Event::ToggleLayer(layer) => self.ppu.toggle_layer(layer),
+                Event::SpeedUp | Event::SpeedDown => {
+                    let step = if event == Event::SpeedUp {
+                        SPEED_STEP
+                    } else {
+                        -SPEED_STEP
+                    };
+                    self.set_speed(self.speed + step);
+                    println!("Speed: {}x", self.speed);
+                }
+                Event::ButtonDown(button) | Event::ButtonUp(button) => {
+                    if self.replay.is_some() {
+                        continue;
+                    }
+                    let pressed = event == Event::ButtonDown(button);
+                    if let Some(recording) = &mut self.recording {
+                        // Key repeats don't change anything
+                        if self.mmu.input.is_pressed(button) != pressed {
+                            recording.push(ReplayInput {
+                                frame: self.frame,
+                                button,
+                                pressed,
+                            });
+                        }
+                    }
+                    self.mmu.handle_input_event(&event);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn run(&mut self, debug: bool) -> Result<(), String> {
+        let mut now = Instant::now();
+        let mut frame_time = Instant::now();
+        let mut pause_at_frame = false;
+        let mut run_to_break = false;
+        let mut first_frame_rendered = false;
+
+        'running: loop {
+            if self.paused {
+                // Keep the window responsive and showing the last frame
+                if !self.handle_events()? {
+                    break 'running;
+                }
+                self.check_rom_watch();
+                self.renderer.render_frame(self.ppu.framebuffer())?;
+                if !self.renderer.paces_frames() {
+                    std::thread::sleep(self.frame_duration());
+                }
+                now = Instant::now();
+                continue;
+            }
+
+            let new_frame = if debug && first_frame_rendered {
+                self.step_with_undo()?
+            } else {
+                self.step()?
+            };
+
+            if new_frame {
+                // Only check for events if the PPU rendered a frame
+                first_frame_rendered = true;
+                if !self.handle_events()? {
+                    break 'running;
+                }
+                for view in &mut self.debug_views {
+                    view.draw(&self.mmu, self.ppu.palette())?;
+                }
+                if let Some(history) = self.apu.history() {
+                    for view in &mut self.audio_views {
+                        view.draw(history)?;
+                    }
+                }
+                // A failed save shouldn't end the game, and the next one
+                // may work
+                if let Err(err) = self.autosave() {
+                    eprintln!("{err}");
+                }
+                self.check_rom_watch();
+                // A VSync'd renderer already blocked until the display's refresh
+                let frame_elapsed = frame_time.elapsed();
+                if self.renderer.paces_frames() {
+                    // Nothing to wait for
+                } else if frame_elapsed < self.frame_duration() {
+                    std::thread::sleep(self.frame_duration() - frame_elapsed);
+                } else {
+                    // println!("Frame took too long: {:?}", frame_elapsed);
+                }
+                frame_time = Instant::now();
+            }
+            let mut time_elapsed = now.elapsed();
+            let div_period = Duration::from_nanos(1_000_000_000 / DIV_RATE).div_f64(self.speed);
+            while time_elapsed > div_period {
+                self.mmu.inc_div();
+                self.apu.inc_div_apu(&self.mmu);
+                time_elapsed -= div_period;
+                now = Instant::now();
+            }
+
+            // if self.mmu[0xFF01] != 0 {
+            //     print!("{}", self.mmu[0xFF01] as char);
+            //     self.mmu[0xFF01] = 0;
+            // }
+            if debug && first_frame_rendered {
+                if self.report_break() {
+                    pause_at_frame = false;
+                    run_to_break = false;
+                } else if run_to_break {
+                    continue;
+                }
+                if pause_at_frame && !new_frame {
+                    pause_at_frame = false;
+                    continue;
+                }
+
+                loop {
+                    self.log_state();
+                    let mut input = String::new();
+                    std::io::stdin().read_line(&mut input).unwrap();
+                    match input.trim() {
+                        "q" => break 'running,
+                        "f" => {
+                            pause_at_frame = true;
+                        }
+                        "s" => {}
+                        // Run until a breakpoint
+                        "c" => {
+                            run_to_break = true;
+                        }
+                        // e.g. `break-op 76` stops after every HALT
+                        command if command.starts_with("break-op ") => {
+                            if let Err(err) = self.break_op_command(&command[9..]) {
+                                println!("{err}");
+                            }
+                            continue;
+                        }
+                        // Run until PC reaches an address or symbol, once
+                        command if command.starts_with("run-to ") => {
+                            match self.parse_location(&command[7..]) {
+                                Ok(location) => {
+                                    self.cpu.run_to(location);
+                                    run_to_break = true;
+                                }
+                                Err(err) => {
+                                    println!("{err}");
+                                    continue;
+                                }
+                            }
+                        }
+                        // Stop at an address or symbol every time
+                        command if command.starts_with("break ") => {
+                            match self.parse_location(&command[6..]) {
+                                Ok(location) => self.address_breakpoints.push(location),
+                                Err(err) => println!("{err}"),
+                            }
+                            continue;
+                        }
+                        command if command.starts_with("break-int ") => {
+                            match Interrupt::parse(&command[10..]) {
+                                Ok(interrupt) => self.cpu.add_interrupt_breakpoint(interrupt),
+                                Err(err) => println!("{err}"),
+                            }
+                            continue;
+                        }
+                        // Step over a call, then ask again
+                        "n" | "next" => {
+                            self.step_over()?;
+                            continue;
+                        }
+                        // Go back an instruction and ask again
+                        "u" | "undo" => {
+                            if !self.undo() {
+                                println!("Nothing to undo");
+                            }
+                            continue;
+                        }
+                        "regs" => {
+                            let registers = self.cpu.registers();
+                            println!("{:?} flags: {}", registers, registers.flags());
+                            continue;
+                        }
+                        // e.g. `set hl c000`
+                        command if command.starts_with("set ") => {
+                            if let Err(err) = self.set_register_command(&command[4..]) {
+                                println!("{err}");
+                            }
+                            continue;
+                        }
+                        "palette" => {
+                            println!("BGP:  {:#04X}", self.mmu.get(0xFF47));
+                            for which in 0..2 {
+                                let shades = self.mmu.object_palette(which);
+                                println!("OBP{which}: {:?} (color 0 transparent)", shades);
+                            }
+                            continue;
+                        }
+                        // e.g. `dump c000 e000 wram.bin`, `load vram.bin 8000`
+                        command if command.starts_with("dump ") => {
+                            if let Err(err) = self.dump_command(&command[5..]) {
+                                println!("{err}");
+                            }
+                            continue;
+                        }
+                        command if command.starts_with("load ") => {
+                            if let Err(err) = self.load_command(&command[5..]) {
+                                println!("{err}");
+                            }
+                            continue;
+                        }
+                        "oam" => {
+                            print!("{}", self.mmu.dump_oam());
+                            continue;
+                        }
+                        "map 0" => {
+                            print!("{}", self.mmu.dump_tilemap(0));
+                            continue;
+                        }
+                        "map 1" => {
+                            print!("{}", self.mmu.dump_tilemap(1));
+                            continue;
+                        }
+                        "b" => {
+                            println!(
+                                "ROM bank: {:#04X}, RAM bank: {:#04X}",
+                                self.mmu.current_rom_bank(),
+                                self.mmu.current_ram_bank()
+                            );
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    break;
+                }
+            }
+        }
+        #[cfg(feature = "apng")]
+        self.stop_apng()?;
+        self.write_save()?;
+        Ok(())
+    }
+}
+
+// A hex number, with or without a leading 0x or 0X
+fn parse_hex(text: &str) -> Result<usize, String> {
+    let hex = text.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(hex, 16).map_err(|_| format!("Invalid value {text}"))
+}
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cartridge that spins in place at 0x0100, with a tile pattern, a
+    // background map and one sprite loaded straight into VRAM and OAM
+    fn pattern_mmu() -> Mmu {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        for row in 0..8u16 {
+            mmu.set(0x8010 + row * 2, 0x55 << (row & 1));
+            mmu.set(0x8011 + row * 2, 0x0F);
+            mmu.set(0x8020 + row * 2, 0xFF >> row);
+            mmu.set(0x8021 + row * 2, 0xFF << row);
+        }
+        for tile in 0..0x400u16 {
+            mmu.set(0x9800 + tile, (tile % 3) as u8);
+        }
+        mmu.set(0xFE00, 16 + 40);
+        mmu.set(0xFE01, 8 + 60);
+        mmu.set(0xFE02, 2);
+        mmu.set(0xFE03, 0x20);
+        mmu.set(0xFF40, 0x93);
+        mmu.set(0xFF47, 0xE4);
+        mmu.set(0xFF48, 0xD2);
+        mmu
+    }
+
+    // A Write that can still be read after the emulator has taken it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_starts_at_address() {
+        let mut rom = vec![0u8; 0x8000];
+        // NOP; NOP; JP 0x0100
+        rom[0x100..0x105].copy_from_slice(&[0x00, 0x00, 0xC3, 0x00, 0x01]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        let out = SharedBuffer::default();
+        emulator.set_trace(Box::new(out.clone()), Some(0x0102));
+        for _ in 0..5 {
+            emulator.step().unwrap();
+        }
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let pcs: Vec<&str> = text
+            .lines()
+            .map(|line| &line[line.find("PC: 00:").unwrap() + 7..][..4])
+            .collect();
+        assert_eq!(pcs, ["0102", "0100", "0101"]);
+        assert!(text.lines().next().unwrap().contains("JP"));
+    }
+
+    #[test]
+    fn test_cpu_snapshot_after_headless_run() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD BC, 0x0305; LD DE, 0x080D; LD HL, 0x1522; JR -2
+        rom[0x100..0x10B].copy_from_slice(&[
+            0x01, 0x05, 0x03, 0x11, 0x0D, 0x08, 0x21, 0x22, 0x15, 0x18, 0xFE,
+        ]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.run_frames(1).unwrap();
+        let registers = emulator.cpu_snapshot();
+        let fibonacci = [
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+        ];
+        assert_eq!(fibonacci, [3, 5, 8, 13, 21, 34]);
+        assert_eq!(registers.pc, 0x0109);
+    }
+
+    #[test]
+    fn test_step_over_runs_until_call_returns() {
+        let mut rom = vec![0u8; 0x8000];
+        // CALL 0x0200; LD B, 0x01; JR -2
+        rom[0x100..0x107].copy_from_slice(&[0xCD, 0x00, 0x02, 0x06, 0x01, 0x18, 0xFE]);
+        // LD C, 0x05; CALL 0x0300; RET
+        rom[0x200..0x206].copy_from_slice(&[0x0E, 0x05, 0xCD, 0x00, 0x03, 0xC9]);
+        // LD D, 0x07; RET
+        rom[0x300..0x303].copy_from_slice(&[0x16, 0x07, 0xC9]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        let sp = emulator.cpu_snapshot().sp;
+
+        emulator.step_over().unwrap();
+        let registers = emulator.cpu_snapshot();
+        assert_eq!(registers.pc, 0x0103);
+        assert_eq!(registers.sp, sp);
+        assert_eq!((registers.c, registers.d), (0x05, 0x07));
+
+        // Anything else is a single step
+        emulator.step_over().unwrap();
+        assert_eq!(emulator.cpu_snapshot().pc, 0x0105);
+        assert_eq!(emulator.cpu_snapshot().b, 0x01);
+    }
+
+    #[test]
+    fn test_symbols_name_debugger_locations() {
+        let mut rom = vec![0u8; 0x8000];
+        // JP Main; Main: INC B; JR Main
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x153].copy_from_slice(&[0x04, 0x18, 0xFD]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.set_symbols(Symbols::parse("00:0150 Main\n00:0151 Main.loop").unwrap());
+
+        assert_eq!(emulator.parse_location("Main").unwrap().address, 0x0150);
+        assert_eq!(emulator.parse_location("0x0151"), Ok(Location::new(0x0151)));
+        assert_eq!(emulator.parse_location("0X0151"), Ok(Location::new(0x0151)));
+        assert!(emulator.parse_location("Nowhere").is_err());
+        assert_eq!(
+            emulator.describe_address(0x0152).as_deref(),
+            Some("Main.loop+0x1")
+        );
+
+        emulator.address_breakpoints.push(Location::new(0x0150));
+        emulator.step().unwrap();
+        assert!(emulator.report_break());
+        emulator.step().unwrap();
+        assert!(!emulator.report_break());
+        emulator.step().unwrap();
+        assert!(emulator.report_break());
+    }
+
+    #[test]
+    fn test_symbol_breakpoints_only_stop_in_their_bank() {
+        // An MBC1 with four banks, each starting with JR -2
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x147] = 0x01;
+        for bank in 1..4 {
+            rom[bank * 0x4000..bank * 0x4000 + 2].copy_from_slice(&[0x18, 0xFE]);
+        }
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.set_symbols(Symbols::parse("02:4000 Banked").unwrap());
+        let location = emulator.parse_location("Banked").unwrap();
+        emulator.address_breakpoints.push(location);
+        emulator.cpu.set_register(Reg::PC, 0x4000);
+
+        emulator.step().unwrap();
+        assert!(!emulator.report_break(), "bank 1 is mapped");
+        emulator.mmu.set(0x2000, 0x02);
+        emulator.step().unwrap();
+        assert!(emulator.report_break());
+
+        emulator.address_breakpoints.clear();
+        emulator.mmu.set(0x2000, 0x03);
+        emulator.cpu.run_to(location);
+        emulator.step().unwrap();
+        assert!(!emulator.report_break(), "bank 3 is mapped");
+        emulator.mmu.set(0x2000, 0x02);
+        emulator.step().unwrap();
+        assert!(emulator.report_break());
+    }
+
+    #[test]
+    fn test_reti_services_pending_interrupt_without_ei_delay() {
+        let mut rom = vec![0u8; 0x8000];
+        // EI; NOP; NOP; JR -2
+        rom[0x100..0x105].copy_from_slice(&[0xFB, 0x00, 0x00, 0x18, 0xFE]);
+        // Timer handler: NOP; RETI
+        rom[0x50..0x52].copy_from_slice(&[0x00, 0xD9]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(0xFFFF, 0x04);
+        mmu.set(0xFF0F, 0x04);
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        let sp = emulator.cpu_snapshot().sp;
+
+        // The NOP after EI runs before the interrupt is taken
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu_snapshot().pc, 0x0102);
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu_snapshot().pc, 0x0051);
+
+        // Requested again inside the handler, it waits for RETI and is
+        // taken straight after, before the NOP at 0x0102
+        emulator.mmu.set(0xFF0F, 0x04);
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu_snapshot().pc, 0x0102);
+        emulator.step().unwrap();
+        let registers = emulator.cpu_snapshot();
+        assert_eq!(registers.pc, 0x0051);
+        assert_eq!(registers.sp, sp - 2);
+        assert_eq!(emulator.mmu.get(sp as usize - 2), 0x02);
+    }
+
+    #[test]
+    fn test_undo_restores_registers_and_memory() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, 0x42; LD (0xC000), A
+        rom[0x100..0x105].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        let before = emulator.cpu.trace(&emulator.mmu);
+        emulator.step_with_undo().unwrap();
+        let after_load = emulator.cpu.trace(&emulator.mmu);
+        emulator.step_with_undo().unwrap();
+        assert_eq!(emulator.mmu.get(0xC000), 0x42);
+
+        assert!(emulator.undo());
+        assert_eq!(emulator.mmu.get(0xC000), 0x00);
+        assert_eq!(emulator.cpu.trace(&emulator.mmu), after_load);
+        assert!(emulator.undo());
+        assert_eq!(emulator.cpu.trace(&emulator.mmu), before);
+        assert!(!emulator.undo());
+    }
+
+    // Whether A reads as pressed through the joypad register
+    fn a_pressed(emulator: &mut Emulator) -> bool {
+        emulator.mmu.set(0xFF00, 0x10);
+        emulator.mmu.get(0xFF00) & 0x01 == 0
+    }
+
+    #[test]
+    fn test_replay_presses_at_frame_start() {
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.set_replay(Replay::parse("gumball-replay 1\n1 a down\n2 a up").unwrap());
+        assert!(!a_pressed(&mut emulator));
+        emulator.run_frames(1).unwrap();
+        assert!(a_pressed(&mut emulator));
+        emulator.run_frames(1).unwrap();
+        assert!(!a_pressed(&mut emulator));
+
+        emulator.press(Button::A);
+        assert!(a_pressed(&mut emulator));
+        emulator.release(Button::A);
+        assert!(!a_pressed(&mut emulator));
+    }
+
+    #[test]
+    fn test_load_rom_starts_clean() {
+        // LD A, 0x42; LD (0xC000), A; JR -2
+        let mut first = vec![0u8; 0x8000];
+        first[0x100..0x107].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&first).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.mmu.set(0xFF47, 0x1B);
+        emulator.run_frames(1).unwrap();
+        assert_eq!(emulator.mmu.get(0xC000), 0x42);
+        assert_eq!(emulator.cpu.trace(&emulator.mmu).a, 0x42);
+
+        let mut second = vec![0u8; 0x8000];
+        second[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        emulator.load_rom(&second).unwrap();
+        let trace = emulator.cpu.trace(&emulator.mmu);
+        assert_eq!(trace.pc, 0x100);
+        assert_eq!(trace.a, 0x01);
+        assert_eq!(emulator.mmu.get(0xC000), 0x00);
+        assert_eq!(emulator.mmu.get(0xFF47), 0xFC);
+        assert_eq!(emulator.mmu.get(0x0101), 0xFE);
+
+        assert!(emulator.load_rom(&[0u8; 0x10]).is_err());
+        assert_eq!(emulator.mmu.get(0x0101), 0xFE);
+    }
+
+    #[test]
+    fn test_stat_write_bug_survives_reset_and_load_rom() {
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.mmu.set_stat_write_bug(true);
+        emulator.reset();
+        assert!(emulator.mmu.stat_write_bug());
+        let rom = vec![0u8; 0x8000];
+        emulator.load_rom(&rom).unwrap();
+        assert!(emulator.mmu.stat_write_bug());
+    }
+
+    #[test]
+    fn test_blocked_directions_survive_reset_and_load_rom() {
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.mmu.input.set_block_opposite_directions(true);
+        emulator.reset();
+        assert!(emulator.mmu.input.blocks_opposite_directions());
+        let rom = vec![0u8; 0x8000];
+        emulator.load_rom(&rom).unwrap();
+        assert!(emulator.mmu.input.blocks_opposite_directions());
+    }
+
+    #[test]
+    fn test_autosave_writes_only_dirty_battery_ram() {
+        // Enable RAM, then LD A, 0x42 and store it to 0xA000 over and over
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x10C].copy_from_slice(&[
+            0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x42, 0xEA, 0x00, 0xA0, 0x18, 0xFB,
+        ]);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let path = std::env::temp_dir().join(format!("gumball-{}.sav", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.set_save_file(&path, Duration::from_nanos(1));
+
+        emulator.autosave().unwrap();
+        assert!(!path.exists(), "nothing written yet");
+        emulator.run_frames(1).unwrap();
+        emulator.autosave().unwrap();
+        let save = std::fs::read(&path).unwrap();
+        assert_eq!((save.len(), save[0]), (0x2000, 0x42));
+        std::fs::remove_file(&path).unwrap();
+
+        emulator.set_save_file(&path, Duration::ZERO);
+        emulator.run_frames(1).unwrap();
+        emulator.autosave().unwrap();
+        assert!(!path.exists(), "a zero interval only saves on exit");
+        emulator.write_save().unwrap();
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // Unsaved RAM is written before a reset
+        emulator.run_frames(1).unwrap();
+        emulator.reset();
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watched_rom_reloads_after_debounce() {
+        let dir = std::env::temp_dir();
+        let rom_path = dir.join(format!("gumball-watch-{}.gb", std::process::id()));
+        let save_path = rom_path.with_extension("sav");
+        // JR -2 at 0x0100, on an MBC1 with battery-backed RAM
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, &rom).unwrap();
+        let mut save = vec![0u8; 0x2000];
+        save[0] = 0x42;
+        std::fs::write(&save_path, &save).unwrap();
+
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        let mut emulator = Emulator::headless(mmu).unwrap();
+        emulator.set_save_file(&save_path, Duration::ZERO);
+        emulator.watch_rom(&rom_path);
+        emulator.run_frames(1).unwrap();
+        emulator.check_rom_watch();
+        assert_ne!(emulator.frame, 0, "unchanged, so not reloaded");
+
+        // Rebuilt with a NOP first, and a newer modification time
+        rom[0x100..0x103].copy_from_slice(&[0x00, 0x18, 0xFE]);
+        std::fs::write(&rom_path, &rom).unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&rom_path)
+            .unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        emulator.check_rom_watch();
+        assert_ne!(emulator.frame, 0, "waits for the debounce");
+
+        let watch = emulator.rom_watch.as_mut().unwrap();
+        let (modified, _) = watch.pending.unwrap();
+        watch.pending = Some((modified, Instant::now() - WATCH_DEBOUNCE));
+        emulator.check_rom_watch();
+        assert_eq!(emulator.frame, 0);
+        assert_eq!(emulator.mmu[0x100], 0x00);
+        assert!(emulator.save_file.is_some());
+        emulator.mmu.set(0x0000, 0x0A);
+        assert_eq!(emulator.mmu.get(0xA000), 0x42);
+
+        std::fs::remove_file(&rom_path).unwrap();
+        std::fs::remove_file(&save_path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_with_new_ram_size_keeps_save_file() {
+        let save_path =
+            std::env::temp_dir().join(format!("gumball-resize-{}.sav", std::process::id()));
+        std::fs::write(&save_path, vec![0x42u8; 0x2000]).unwrap();
+        // MBC1 with 32 KB of battery-backed RAM, where the save is 8 KB
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x03;
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.set_save_file(&save_path, Duration::ZERO);
+
+        emulator.reload_rom(&rom).unwrap();
+        assert_eq!(emulator.mmu[0x147], 0x03);
+        assert!(emulator.save_file.is_some());
+        emulator.mmu.set(0x0000, 0x0A);
+        assert_eq!(emulator.mmu.get(0xA000), 0x00);
+        // The new game's RAM replaces the old save
+        emulator.mmu.set(0xA000, 0x17);
+        emulator.write_save().unwrap();
+        let save = std::fs::read(&save_path).unwrap();
+        assert_eq!((save.len(), save[0]), (0x8000, 0x17));
+        std::fs::remove_file(&save_path).unwrap();
+    }
+
+    struct ScriptedEvents(std::collections::VecDeque<Event>);
+
+    impl EventQueue for ScriptedEvents {
+        fn poll_event(&mut self) -> Option<Event> {
+            self.0.pop_front()
+        }
+    }
+
+    #[test]
+    fn test_recording_skips_repeats() {
+        let events = [
+            Event::ButtonDown(Button::A),
+            Event::ButtonDown(Button::A),
+            Event::ButtonUp(Button::A),
+            Event::Quit,
+        ];
+        let mut emulator = Emulator::new(
+            Box::new(NullRenderer),
+            Box::new(ScriptedEvents(events.into())),
+            pattern_mmu(),
+            None,
+        )
+        .unwrap();
+        emulator.start_recording();
+        emulator.run(false).unwrap();
+        assert_eq!(
+            emulator.take_recording().unwrap().to_string(),
+            "gumball-replay 1\n1 a down\n1 a up\n"
+        );
+    }
+
+    #[test]
+    fn test_reset_restarts_recording_and_replay() {
+        let events = [
+            Event::ButtonDown(Button::A),
+            Event::Quit,
+            Event::ButtonDown(Button::B),
+            Event::Quit,
+        ];
+        let mut emulator = Emulator::new(
+            Box::new(NullRenderer),
+            Box::new(ScriptedEvents(events.into())),
+            pattern_mmu(),
+            None,
+        )
+        .unwrap();
+        emulator.start_recording();
+        emulator.run_frames(10).unwrap();
+        emulator.run(false).unwrap();
+        emulator.reset();
+        emulator.run(false).unwrap();
+        assert_eq!(
+            emulator.take_recording().unwrap().to_string(),
+            "gumball-replay 1\n1 b down\n"
+        );
+
+        emulator.set_replay(Replay::parse("gumball-replay 1\n1 a down").unwrap());
+        emulator.run_frames(5).unwrap();
+        emulator.reset();
+        assert!(!a_pressed(&mut emulator));
+        emulator.run_frames(1).unwrap();
+        assert!(a_pressed(&mut emulator));
+    }
+
+    struct CountingSink(std::rc::Rc<std::cell::Cell<usize>>);
+
+    impl AudioSink for CountingSink {
+        fn sample_rate(&self) -> i32 {
+            32768
+        }
+
+        fn queue_samples(&mut self, samples: &[[f32; 2]]) {
+            self.0.set(self.0.get() + samples.len());
+        }
+    }
+
+    #[test]
+    fn test_audio_sink_receives_samples() {
+        let queued = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut emulator = Emulator::new(
+            Box::new(NullRenderer),
+            Box::new(NoEvents),
+            pattern_mmu(),
+            Some(Box::new(CountingSink(queued.clone()))),
+        )
+        .unwrap();
+        emulator.run_frames(1).unwrap();
+        assert!(queued.get() > 0);
+    }
+
+    #[test]
+    fn test_speed_scales_samples_per_frame() {
+        let samples_at = |speed: f64| {
+            let queued = std::rc::Rc::new(std::cell::Cell::new(0));
+            let mut emulator = Emulator::new(
+                Box::new(NullRenderer),
+                Box::new(NoEvents),
+                pattern_mmu(),
+                Some(Box::new(CountingSink(queued.clone()))),
+            )
+            .unwrap();
+            emulator.set_speed(speed);
+            emulator.run_frames(4).unwrap();
+            queued.get()
+        };
+        let normal = samples_at(1.0);
+        let double = samples_at(2.0);
+        assert!(double * 2 >= normal - 8 && double * 2 <= normal + 8);
+    }
+
+    #[test]
+    fn test_status_json_describes_machine() {
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.apu.toggle_channel(2);
+        emulator.run_frames(1).unwrap();
+        let status: serde_json::Value = serde_json::from_str(&emulator.status_json()).unwrap();
+        assert_eq!(status["frame"], 1);
+        assert_eq!(status["cpu"]["pc"], 0x0100);
+        assert_eq!(status["cpu"]["sp"], 0xFFFE);
+        assert_eq!(status["ppu"]["ly"], emulator.mmu.get(LY));
+        assert_eq!(status["apu"]["channels"][1]["muted"], true);
+        assert_eq!(status["apu"]["channels"][0]["muted"], false);
+        assert_eq!(status["banks"]["rom"], 1);
+        assert_eq!(status["interrupts"]["ie"], emulator.mmu.get(0xFFFF));
+    }
+
+    #[test]
+    fn test_speed_is_clamped() {
+        let mut emulator = Emulator::headless(pattern_mmu()).unwrap();
+        emulator.set_speed(10.0);
+        assert_eq!(emulator.speed(), 4.0);
+        emulator.set_speed(0.0);
+        assert_eq!(emulator.speed(), 0.25);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::core::apu::ChannelHistory;
+use crate::core::input::Button;
+use crate::core::mmu::Mmu;
+use crate::core::ppu::{Colors, Layer};
+
+// Presents finished frames. `pixels` holds the 160x144 screen as RGB24 rows.
+pub trait Renderer {
+    fn render_frame(&mut self, pixels: &[u8]) -> Result<(), String>;
+
+    // True if `render_frame` blocks until the display is ready for the next
+    // frame (VSync), in which case the emulator loop doesn't sleep itself
+    fn paces_frames(&self) -> bool {
+        false
+    }
+}
+
+// What a frontend can ask of the emulator loop, already mapped from whatever
+// keys or buttons it uses
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Event {
+    Quit,
+    ButtonDown(Button),
+    ButtonUp(Button),
+    TogglePause,
+    // Run one instruction while paused
+    Step,
+    // Mute or unmute an APU channel (1-4)
+    ToggleChannel(usize),
+    // Run faster or slower than the real hardware
+    SpeedUp,
+    SpeedDown,
+    // Start or stop recording the screen to an animated PNG
+    ToggleApng,
+    // Hide or show a layer of the picture, for debugging
+    ToggleLayer(Layer),
+}
+
+// Source of input events for the emulator loop
+pub trait EventQueue {
+    fn poll_event(&mut self) -> Option<Event>;
+}
+
+// Throws every frame away, for running without a screen
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn render_frame(&mut self, _pixels: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Never has any input, for running without a frontend
+pub struct NoEvents;
+
+impl EventQueue for NoEvents {
+    fn poll_event(&mut self) -> Option<Event> {
+        None
+    }
+}
+
+// Plays the APU's output: stereo `[left, right]` frames at `sample_rate` Hz
+pub trait AudioSink {
+    fn sample_rate(&self) -> i32;
+    fn queue_samples(&mut self, samples: &[[f32; 2]]);
+}
+
+// A debug display redrawn from memory after every frame
+pub trait DebugView {
+    fn draw(&mut self, mem: &Mmu, colors: &Colors) -> Result<(), String>;
+}
+
+// A debug display of the APU's channels, redrawn after every frame
+pub trait AudioView {
+    fn draw(&mut self, history: &ChannelHistory) -> Result<(), String>;
+}
@@ -0,0 +1,262 @@
+use crate::core::frontend::Event;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Button {
+    A,
+    B,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    // Parse a button name as used in replay files, ignoring case
+    pub fn from_name(name: &str) -> Option<Button> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            "start" => Some(Button::Start),
+            "select" => Some(Button::Select),
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "left" => Some(Button::Left),
+            "right" => Some(Button::Right),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::Start => "start",
+            Button::Select => "select",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Input {
+    select_button_keys: bool,
+    select_direction_keys: bool,
+    a: bool,
+    b: bool,
+    start: bool,
+    select: bool,
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+    // A real d-pad can't press opposite directions at once, but a keyboard
+    // can. With this set, Left+Right reads as neither, and so does Up+Down.
+    block_opposite_directions: bool,
+}
+
+impl Input {
+    pub fn set_block_opposite_directions(&mut self, block: bool) {
+        self.block_opposite_directions = block;
+    }
+
+    pub fn blocks_opposite_directions(&self) -> bool {
+        self.block_opposite_directions
+    }
+
+    // Up, down, left and right as the game sees them
+    fn directions(&self) -> (bool, bool, bool, bool) {
+        let (mut up, mut down) = (self.up, self.down);
+        let (mut left, mut right) = (self.left, self.right);
+        if self.block_opposite_directions {
+            if up && down {
+                (up, down) = (false, false);
+            }
+            if left && right {
+                (left, right) = (false, false);
+            }
+        }
+        (up, down, left, right)
+    }
+
+    pub fn read_ff00(&self) -> u8 {
+        // Bits 7-6 always read 1, and bits 5-4 read back the selection lines,
+        // which are low when selected. The button lines are pulled up and
+        // any pressed button in a selected group pulls its line low, so with
+        // both groups selected they're wired together (ANDed).
+        let mut result = 0xFF;
+
+        // Update the result based on the button selection bits
+        if self.select_button_keys {
+            result &= !(1 << 5); // Clear bit 5 if button keys are selected
+            if self.start {
+                result &= !(1 << 3);
+            }
+            if self.select {
+                result &= !(1 << 2);
+            }
+            if self.b {
+                result &= !(1 << 1);
+            }
+            if self.a {
+                result &= !(1 << 0);
+            }
+        }
+
+        if self.select_direction_keys {
+            result &= !(1 << 4); // Clear bit 4 if direction keys are selected
+            let (up, down, left, right) = self.directions();
+            if down {
+                result &= !(1 << 3);
+            }
+            if up {
+                result &= !(1 << 2);
+            }
+            if left {
+                result &= !(1 << 1);
+            }
+            if right {
+                result &= !(1 << 0);
+            }
+        }
+
+        result
+    }
+
+    pub fn write_ff00(&mut self, value: u8) {
+        // Only bits 5 and 4 are relevant for selecting the keys
+        self.select_button_keys = value & (1 << 5) == 0;
+        self.select_direction_keys = value & (1 << 4) == 0;
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::A => self.a,
+            Button::B => self.b,
+            Button::Select => self.select,
+            Button::Start => self.start,
+            Button::Right => self.right,
+            Button::Left => self.left,
+            Button::Up => self.up,
+            Button::Down => self.down,
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.press_button(button);
+        } else {
+            self.release_button(button);
+        }
+    }
+
+    // Methods to update button states
+    fn press_button(&mut self, button: Button) {
+        match button {
+            Button::A => self.a = true,
+            Button::B => self.b = true,
+            Button::Select => self.select = true,
+            Button::Start => self.start = true,
+            Button::Right => self.right = true,
+            Button::Left => self.left = true,
+            Button::Up => self.up = true,
+            Button::Down => self.down = true,
+        }
+    }
+
+    fn release_button(&mut self, button: Button) {
+        match button {
+            Button::A => self.a = false,
+            Button::B => self.b = false,
+            Button::Select => self.select = false,
+            Button::Start => self.start = false,
+            Button::Right => self.right = false,
+            Button::Left => self.left = false,
+            Button::Up => self.up = false,
+            Button::Down => self.down = false,
+        }
+    }
+
+    // Returns true if the event pulled one of the selected input lines low,
+    // which should request a joypad interrupt
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let before = self.read_ff00();
+        match *event {
+            Event::ButtonDown(button) => self.set_button(button, true),
+            Event::ButtonUp(button) => self.set_button(button, false),
+            _ => {}
+        }
+        before & !self.read_ff00() & 0x0F != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directions_with(block: bool, pressed: &[Button]) -> u8 {
+        let mut input = Input::default();
+        input.set_block_opposite_directions(block);
+        input.write_ff00(0x20);
+        for &button in pressed {
+            input.set_button(button, true);
+        }
+        input.read_ff00() & 0x0F
+    }
+
+    #[test]
+    fn test_opposite_directions_read_as_neither_when_blocked() {
+        assert_eq!(
+            directions_with(false, &[Button::Left, Button::Right]),
+            0b1100
+        );
+        assert_eq!(
+            directions_with(true, &[Button::Left, Button::Right]),
+            0b1111
+        );
+        assert_eq!(directions_with(true, &[Button::Up, Button::Down]), 0b1111);
+        // Other directions still get through
+        assert_eq!(
+            directions_with(true, &[Button::Up, Button::Down, Button::Left]),
+            0b1101
+        );
+        assert_eq!(directions_with(true, &[Button::Up, Button::Right]), 0b1010);
+    }
+
+    #[test]
+    fn test_ff00_selection_lines() {
+        let mut input = Input::default();
+        input.set_button(Button::A, true);
+        input.set_button(Button::Left, true);
+        // Neither group selected
+        input.write_ff00(0x30);
+        assert_eq!(input.read_ff00(), 0xFF);
+        input.write_ff00(0x10);
+        assert_eq!(input.read_ff00(), 0xDE);
+        input.write_ff00(0x20);
+        assert_eq!(input.read_ff00(), 0xED);
+        // Both selected: a line is low if either group's button is pressed
+        input.write_ff00(0x00);
+        assert_eq!(input.read_ff00(), 0xCC);
+        input.set_button(Button::B, true);
+        assert_eq!(input.read_ff00(), 0xCC);
+        input.set_button(Button::Start, true);
+        assert_eq!(input.read_ff00(), 0xC4);
+    }
+
+    #[test]
+    fn test_blocked_press_doesnt_request_interrupt() {
+        let mut input = Input::default();
+        input.set_block_opposite_directions(true);
+        input.write_ff00(0x20);
+        assert!(input.handle_event(&Event::ButtonDown(Button::Left)));
+        assert!(!input.handle_event(&Event::ButtonDown(Button::Right)));
+        // Releasing Left lets Right through
+        assert!(input.handle_event(&Event::ButtonUp(Button::Left)));
+        assert!(input.is_pressed(Button::Right));
+    }
+}
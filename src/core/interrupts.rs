@@ -1,7 +1,7 @@
 use std::ops::BitAnd;
 
-use crate::cpu::Cpu;
-use crate::mmu::Mmu;
+use crate::core::cpu::Cpu;
+use crate::core::mmu::Mmu;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Interrupt {
@@ -68,6 +68,25 @@ impl Interrupt {
         }
     }
 
+    // The interrupt's bit in IE and IF
+    pub fn mask(&self) -> u8 {
+        1 << self.priority()
+    }
+
+    // vblank, lcdstat, timer, serial or joypad
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "vblank" => Ok(Interrupt::VBlank),
+            "lcdstat" => Ok(Interrupt::LcdStat),
+            "timer" => Ok(Interrupt::Timer),
+            "serial" => Ok(Interrupt::Serial),
+            "joypad" => Ok(Interrupt::Joypad),
+            _ => Err(format!(
+                "Expected vblank, lcdstat, timer, serial or joypad, got {text}"
+            )),
+        }
+    }
+
     pub fn enabled(&self, mem: &Mmu) -> bool {
         let ie = mem.get(0xFFFF) & (1 << self.priority()) != 0;
         let if_ = mem.get(0xFF0F) & (1 << self.priority()) != 0;
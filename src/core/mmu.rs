@@ -0,0 +1,1591 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::ops::{Index, IndexMut};
+
+use crate::core::cheats::CheatEngine;
+use crate::core::frontend::Event;
+use crate::core::input::{Button, Input};
+use crate::core::interrupts::Interrupt;
+use crate::core::ppu::read_oam;
+use crate::core::registers::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MBC {
+    None,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+}
+
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// The memory bank controller for cartridge type (header byte 0x147) `code`
+fn mbc_for(code: u8) -> Result<MBC, RomError> {
+    match code {
+        0x00 => Ok(MBC::None),
+        0x01..=0x03 => Ok(MBC::MBC1),
+        0x05..=0x06 => Ok(MBC::MBC2),
+        0x0F..=0x13 => Ok(MBC::MBC3),
+        0x19..=0x1E => Ok(MBC::MBC5),
+        code => Err(RomError::UnsupportedMbc(code)),
+    }
+}
+
+// Size in bytes of the cartridge RAM, checked against the cartridge type
+fn cartridge_ram_size(rom: &[u8], mbc: MBC) -> Result<usize, RomError> {
+    let ram_size = if mbc == MBC::MBC2 {
+        // 512 half-bytes, stored a byte each
+        0x200
+    } else {
+        ram_size(rom[0x149]).ok_or(RomError::UnsupportedRamSize(rom[0x149]))?
+    };
+    if ram_size == 0 && has_cartridge_ram(rom[0x147]) {
+        return Err(RomError::MissingRam(rom[0x147]));
+    }
+    Ok(ram_size)
+}
+
+// Size in bytes of the external RAM given by header byte 0x149
+fn ram_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 => Some(0),
+        0x01 => Some(0x800),
+        0x02 => Some(0x2000),
+        0x03 => Some(0x8000),
+        0x04 => Some(0x20000),
+        0x05 => Some(0x10000),
+        _ => None,
+    }
+}
+
+// Cartridge types whose name includes RAM. MBC2's RAM is built in, so its
+// header gives no RAM size.
+fn has_cartridge_ram(cartridge_type: u8) -> bool {
+    matches!(
+        cartridge_type,
+        0x02 | 0x03 | 0x08 | 0x09 | 0x0C | 0x0D | 0x10 | 0x12 | 0x13 | 0x1A | 0x1B | 0x1D | 0x1E
+    )
+}
+
+// Cartridges whose RAM keeps its contents with the power off, so it's
+// worth writing to a .sav file
+fn has_battery(cartridge_type: u8) -> bool {
+    matches!(
+        cartridge_type,
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+    )
+}
+
+// MBC1M multicarts are 1 MB images holding four 256 KB games, each starting
+// with its own header. Finding the logo at the start of the second game is
+// the usual way to tell them apart from ordinary 1 MB MBC1 carts.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(std::io::Error),
+    TooSmall(usize),
+    UnsupportedMbc(u8),
+    UnsupportedRamSize(u8),
+    MissingRam(u8),
+    BadChecksum { expected: u8, actual: u8 },
+    InvalidBootRom(usize),
+    UnsupportedRomSize(u8),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(e) => write!(f, "{e}"),
+            RomError::TooSmall(len) => {
+                write!(
+                    f,
+                    "ROM is {len:#x} bytes, too small to contain a cartridge header"
+                )
+            }
+            RomError::UnsupportedMbc(code) => {
+                write!(f, "Unsupported cartridge type {code:#04x}")
+            }
+            RomError::UnsupportedRamSize(code) => {
+                write!(f, "Unsupported RAM size {code:#04x}")
+            }
+            RomError::MissingRam(code) => {
+                write!(
+                    f,
+                    "Cartridge type {code:#04x} has RAM, but the header gives its size as 0"
+                )
+            }
+            RomError::BadChecksum { expected, actual } => {
+                write!(
+                    f,
+                    "Header checksum is {actual:#04x}, but the header sums to {expected:#04x}"
+                )
+            }
+            RomError::InvalidBootRom(len) => {
+                write!(f, "Boot ROM is {len:#x} bytes, expected exactly 0x100")
+            }
+            RomError::UnsupportedRomSize(code) => {
+                write!(f, "Unsupported ROM size {code:#04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RomError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RomError {
+    fn from(e: std::io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+// What WRAM and HRAM hold at power-on. Real hardware leaves them undefined,
+// and some games seed their random numbers from whatever is there.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum InitRam {
+    #[default]
+    Zero,
+    // Alternating 8-byte runs of 0x00 and 0xFF, like the stripes DMG RAM
+    // often powers on with
+    Pattern,
+    // Pseudo-random bytes from this seed, the same every run
+    Random(u64),
+}
+
+impl InitRam {
+    // `zero`, `pattern`, `random` or `random:<seed>`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.split_once(':') {
+            None if text == "zero" => Ok(InitRam::Zero),
+            None if text == "pattern" => Ok(InitRam::Pattern),
+            None if text == "random" => Ok(InitRam::Random(0)),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(InitRam::Random)
+                .map_err(|_| format!("Invalid seed {seed}")),
+            _ => Err(format!(
+                "Expected zero, pattern, random or random:<seed>, got {text}"
+            )),
+        }
+    }
+
+    fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            InitRam::Zero => ram.fill(0),
+            InitRam::Pattern => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 8 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            InitRam::Random(seed) => {
+                // xorshift64*, which needs a nonzero state
+                let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+                for byte in ram.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *byte = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+                }
+            }
+        }
+    }
+}
+
+// An OAM DMA in progress. It copies 0xA0 bytes from `source` to OAM, one
+// per M-cycle, so it takes 160 M-cycles (640 T-cycles) in all.
+#[derive(Debug, Clone, Copy)]
+struct Dma {
+    source: usize,
+    copied: usize,
+    // T-cycles toward the next byte
+    cycles: u32,
+}
+
+pub struct Mmu {
+    memory: [u8; 0x10000],
+    total_rom: Vec<u8>,
+    total_ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    mbc: MBC,
+    mbc1_multicart: bool,
+    bank_low: u8,
+    bank_high: u8,
+    banking_mode: bool,
+    pub input: Input,
+    pub cheats: CheatEngine,
+    has_external_ram: bool,
+    enable_external_ram: bool,
+    battery: bool,
+    // Set by writes to battery-backed RAM since the last `take_ram_dirty`
+    ram_dirty: bool,
+    boot_rom_mapped: bool,
+    // While recording, the old value of each byte written, for undo
+    journal: Option<Vec<(usize, u8)>>,
+    init_ram: InitRam,
+    // CGB VRAM bank selected through VBK. The selected bank lives in
+    // `memory`, and the other one here.
+    vram_bank: usize,
+    other_vram: Box<[u8; 0x2000]>,
+    dma: Option<Dma>,
+    // Model the DMG quirk where any write to STAT briefly enables every STAT
+    // source, raising a spurious interrupt in HBlank, VBlank or on LY == LYC
+    stat_write_bug: bool,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Mmu {
+            memory: [0u8; 0x10000],
+            total_rom: Vec::new(),
+            total_ram: Vec::new(),
+            rom_bank: 1,
+            ram_bank: 0,
+            mbc: MBC::None,
+            mbc1_multicart: false,
+            bank_low: 0,
+            bank_high: 0,
+            banking_mode: false,
+            input: Input::default(),
+            cheats: CheatEngine::default(),
+            has_external_ram: false,
+            enable_external_ram: false,
+            battery: false,
+            ram_dirty: false,
+            boot_rom_mapped: false,
+            journal: None,
+            init_ram: InitRam::Zero,
+            vram_bank: 0,
+            other_vram: Box::new([0; 0x2000]),
+            dma: None,
+            stat_write_bug: false,
+        }
+    }
+
+    pub fn init() -> Self {
+        Mmu::init_with_ram(InitRam::Zero)
+    }
+
+    // Post-boot state with WRAM (0xC000-0xDFFF) and HRAM (0xFF80-0xFFFE)
+    // filled according to `init_ram`
+    pub fn init_with_ram(init_ram: InitRam) -> Self {
+        let mut mmu = Mmu::new();
+        mmu.init_ram = init_ram;
+        init_ram.fill(&mut mmu.memory[0xC000..0xE000]);
+        init_ram.fill(&mut mmu.memory[0xFF80..0xFFFF]);
+        mmu.memory[0xFF00] = 0xCF;
+        mmu.memory[0xFF01] = 0x00;
+        mmu.memory[0xFF02] = 0x7E;
+        mmu.memory[0xFF04] = 0xAB;
+        mmu.memory[0xFF05] = 0x00;
+        mmu.memory[0xFF06] = 0x00;
+        mmu.memory[0xFF07] = 0x00;
+        mmu.memory[0xFF10] = 0x80;
+        mmu.memory[0xFF11] = 0xBF;
+        mmu.memory[0xFF12] = 0xF3;
+        mmu.memory[0xFF13] = 0xFF;
+        mmu.memory[0xFF14] = 0xBF;
+        mmu.memory[0xFF16] = 0x3F;
+        mmu.memory[0xFF17] = 0x00;
+        mmu.memory[0xFF18] = 0xFF;
+        mmu.memory[0xFF19] = 0xBF;
+        mmu.memory[0xFF1A] = 0x7F;
+        mmu.memory[0xFF1B] = 0xFF;
+        mmu.memory[0xFF1C] = 0x9F;
+        mmu.memory[0xFF1D] = 0xFF;
+        mmu.memory[0xFF1E] = 0xBF;
+        mmu.memory[0xFF20] = 0xFF;
+        mmu.memory[0xFF21] = 0x00;
+        mmu.memory[0xFF22] = 0x00;
+        mmu.memory[0xFF23] = 0xBF;
+        mmu.memory[0xFF24] = 0x77;
+        mmu.memory[0xFF25] = 0xF3;
+        mmu.memory[0xFF26] = 0xF1;
+        mmu.memory[0xFF40] = 0x91;
+        mmu.memory[0xFF41] = 0x85;
+        mmu.memory[0xFF42] = 0x00;
+        mmu.memory[0xFF43] = 0x00;
+        mmu.memory[0xFF44] = 0x90;
+        mmu.memory[0xFF45] = 0x00;
+        mmu.memory[0xFF46] = 0xFF;
+        mmu.memory[0xFF47] = 0xFC;
+        mmu.memory[0xFF48] = 0xFF;
+        mmu.memory[0xFF49] = 0xFF;
+        mmu.memory[0xFF4A] = 0x00;
+        mmu.memory[0xFF4B] = 0x00;
+        mmu.memory[0xFFFF] = 0x00;
+        mmu
+    }
+
+    // Place a headerless program at 0x0100 with no banking, for tests. It has
+    // to fit in the fixed 32 KB ROM area; use `initialize_from_bytes` for
+    // full cartridges, which handles larger ROMs through the MBC.
+    pub fn init_with_vec(rom: Vec<u8>) -> Self {
+        let mut mmu = Mmu::init();
+        let len = rom.len();
+        if len > 0x8000 - 0x100 {
+            panic!("Program of {len:#x} bytes does not fit in 0x0100..0x8000");
+        }
+        mmu.memory[0x0100..0x100 + len].copy_from_slice(&rom);
+        mmu
+    }
+
+    // Short ROMs are zero-padded to 32 KB (and others to a whole number of
+    // 16 KB banks), but anything without a complete header (0x100..0x150) is
+    // rejected.
+    pub fn initialize_from_bytes(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        if rom.len() < 0x150 {
+            return Err(RomError::TooSmall(rom.len()));
+        }
+        let mbc = mbc_for(rom[0x147])?;
+        let ram_size = cartridge_ram_size(rom, mbc)?;
+        self.battery = ram_size > 0 && has_battery(rom[0x147]);
+        self.ram_dirty = false;
+        let mut rom = rom.to_vec();
+        rom.resize(rom.len().max(0x8000).next_multiple_of(0x4000), 0);
+        self.memory[0x0000..0x8000].copy_from_slice(&rom[0..0x8000]);
+        self.mbc1_multicart = mbc == MBC::MBC1 && is_mbc1_multicart(&rom);
+        self.total_rom = rom;
+        self.total_ram = vec![0u8; ram_size];
+        self.has_external_ram = ram_size > 0;
+        self.mbc = mbc;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.bank_low = 0;
+        self.bank_high = 0;
+        self.banking_mode = false;
+        Ok(())
+    }
+
+    // Map a boot ROM over 0x0000-0x00FF until 0xFF50 is written. Call this
+    // after the cartridge is loaded so its first 256 bytes can be restored.
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8]) -> Result<(), RomError> {
+        if boot_rom.len() != 0x100 {
+            return Err(RomError::InvalidBootRom(boot_rom.len()));
+        }
+        self.memory[0x0000..0x0100].copy_from_slice(boot_rom);
+        self.boot_rom_mapped = true;
+        Ok(())
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    fn unmap_boot_rom(&mut self) {
+        self.memory[0x0000..0x0100].copy_from_slice(&self.total_rom[0x0000..0x0100]);
+        self.boot_rom_mapped = false;
+    }
+
+    // Returns the bank actually mapped, after wrapping to the ROM size
+    fn map_rom_bank(&mut self, bank: usize, start: usize) -> usize {
+        let bank = bank % (self.total_rom.len() / 0x4000);
+        let offset = bank * 0x4000;
+        let temp = self.total_rom[offset..offset + 0x4000].to_vec();
+        self.memory[start..start + 0x4000].copy_from_slice(&temp);
+        bank
+    }
+
+    // The bank mapped at 0x4000-0x7FFF
+    pub fn current_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    // The bank mapped at 0xA000-0xBFFF
+    pub fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    // The VRAM bank mapped at 0x8000-0x9FFF. Always 0 on a DMG.
+    pub fn current_vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    // Only CGB games can switch VRAM banks. A DMG ignores VBK.
+    fn switch_vram_bank(&mut self, value: u8) {
+        if !self.cgb_mode() {
+            return;
+        }
+        let bank = (value & 0x01) as usize;
+        if bank != self.vram_bank {
+            self.memory[0x8000..0xA000].swap_with_slice(&mut self.other_vram[..]);
+            self.vram_bank = bank;
+        }
+    }
+
+    // Read from either VRAM bank, whichever is mapped, for the PPU
+    pub fn peek_vram(&self, bank: usize, address: usize) -> u8 {
+        if bank == self.vram_bank {
+            self.memory[address]
+        } else {
+            self.other_vram[address - 0x8000]
+        }
+    }
+
+    fn switch_rom_bank(&mut self, bank: u8) {
+        if self.mbc == MBC::None {
+            return;
+        }
+        let bank = bank & 0x1F;
+        let bank = if self.mbc == MBC::MBC1 {
+            self.bank_low = bank;
+            self.mbc1_upper_bits() | self.mbc1_lower_bits()
+        } else if bank == 0 {
+            1
+        } else {
+            bank as usize
+        };
+        self.rom_bank = self.map_rom_bank(bank, 0x4000);
+    }
+
+    // The zero check looks at all 5 bits, but multicarts only wire up 4 of
+    // them, so selecting bank 0x10 there maps the first bank of the game.
+    fn mbc1_lower_bits(&self) -> usize {
+        let bank = if self.bank_low == 0 { 1 } else { self.bank_low };
+        let mask = if self.mbc1_multicart { 0x0F } else { 0x1F };
+        (bank & mask) as usize
+    }
+
+    fn mbc1_upper_bits(&self) -> usize {
+        let shift = if self.mbc1_multicart { 4 } else { 5 };
+        (self.bank_high as usize) << shift
+    }
+
+    // In mode 1 the secondary register also banks 0x0000-0x3FFF and
+    // external RAM; in mode 0 both stay at bank 0.
+    fn update_mbc1_banks(&mut self) {
+        self.switch_rom_bank(self.bank_low);
+        if !self.boot_rom_mapped {
+            let zero_bank = if self.banking_mode {
+                self.mbc1_upper_bits()
+            } else {
+                0
+            };
+            self.map_rom_bank(zero_bank, 0x0000);
+        }
+        let ram_bank = if self.banking_mode { self.bank_high } else { 0 };
+        self.switch_ram_bank(ram_bank);
+    }
+
+    fn switch_ram_bank(&mut self, bank: u8) {
+        if self.mbc == MBC::None {
+            return;
+        }
+        let mask = if self.mbc == MBC::MBC5 { 0x0F } else { 0x03 };
+        // Banks past the end of the RAM wrap around, and RAM smaller than a
+        // bank only fills the start of 0xA000-0xBFFF
+        let banks = (self.total_ram.len() / 0x2000).max(1);
+        let bank = (bank & mask) as usize % banks;
+        self.save_ram_bank();
+        self.ram_bank = bank;
+        self.load_ram_bank();
+    }
+
+    // Copy the mapped RAM bank back to `total_ram`
+    fn save_ram_bank(&mut self) {
+        let len = self.total_ram.len().min(0x2000);
+        let offset = self.ram_bank * 0x2000;
+        self.total_ram[offset..offset + len].copy_from_slice(&self.memory[0xA000..0xA000 + len]);
+    }
+
+    fn load_ram_bank(&mut self) {
+        let len = self.total_ram.len().min(0x2000);
+        let offset = self.ram_bank * 0x2000;
+        let temp = self.total_ram[offset..offset + len].to_vec();
+        self.memory[0xA000..0xA000 + len].copy_from_slice(&temp);
+    }
+
+    // All of the cartridge's RAM, for writing to a .sav file, or None if it
+    // isn't battery-backed
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mut ram = self.total_ram.clone();
+        let len = ram.len().min(0x2000);
+        let offset = self.ram_bank * 0x2000;
+        ram[offset..offset + len].copy_from_slice(&self.memory[0xA000..0xA000 + len]);
+        Some(ram)
+    }
+
+    // Restore RAM written by `save_data`. Ignored for cartridges without a
+    // battery.
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), String> {
+        if !self.battery {
+            return Ok(());
+        }
+        if data.len() != self.total_ram.len() {
+            return Err(format!(
+                "Save file is {:#x} bytes, but the cartridge has {:#x} bytes of RAM",
+                data.len(),
+                self.total_ram.len()
+            ));
+        }
+        self.total_ram.copy_from_slice(data);
+        self.load_ram_bank();
+        Ok(())
+    }
+
+    // Whether battery-backed RAM was written since the last call
+    pub fn take_ram_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+
+    // Power-cycle: memory and the I/O registers go back to their post-boot
+    // values and the cartridge's first banks are mapped again. External RAM
+    // is battery-backed on real carts, so it survives, as do the cheats and
+    // the STAT write bug and opposite-direction settings.
+    pub fn reset(&mut self) {
+        if self.mbc != MBC::None {
+            self.save_ram_bank();
+        }
+        let rom = std::mem::take(&mut self.total_rom);
+        let ram = std::mem::take(&mut self.total_ram);
+        let cheats = std::mem::take(&mut self.cheats);
+        let stat_write_bug = self.stat_write_bug;
+        let block_opposite_directions = self.input.blocks_opposite_directions();
+        *self = Mmu::init_with_ram(self.init_ram);
+        self.cheats = cheats;
+        self.stat_write_bug = stat_write_bug;
+        self.input
+            .set_block_opposite_directions(block_opposite_directions);
+        if !rom.is_empty() {
+            self.initialize_from_bytes(&rom)
+                .expect("the ROM was checked when it was loaded");
+            self.total_ram = ram;
+            self.load_ram_bank();
+        }
+    }
+
+    // Record every write from here until `take_journal`
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    pub fn take_journal(&mut self) -> Vec<(usize, u8)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    // Put back the old values from a journal. Only the bytes themselves are
+    // restored, not bank switches or other cartridge state.
+    pub fn undo_writes(&mut self, journal: &[(usize, u8)]) {
+        for &(address, old) in journal.iter().rev() {
+            self.memory[address] = old;
+        }
+    }
+
+    pub fn set(&mut self, address: u16, value: u8) {
+        if let Some(journal) = &mut self.journal {
+            let address = echo_ram_address(address as usize);
+            journal.push((address, self.memory[address]));
+        }
+        // match self.mbc {
+        //     MBC::MBC1 => match address {
+        //         0x0000..=0x1FFF => {
+        //             self.enable_external_ram = value == 0x0A;
+        //         }
+        //         0x2000..=0x3FFF => self.switch_rom_bank(value),
+        //         0x4000..=0x5FFF => self.switch_ram_bank(value),
+        //         0xA000..=0xBFFF => {
+        //             if self.enable_external_ram {
+        //                 self.memory[address as usize] = value;
+        //             }
+        //         }
+        //         0xFF00 => self.input.write_ff00(value),
+        //         0xFF04 => self.memory[address as usize] = 0,
+        //         0xFF46 => self.dma_transfer(value),
+        //         address => self.memory[address as usize] = value,
+        //     },
+        // }
+        match address {
+            _ if self.blocked_by_ppu(address as usize) => {}
+            0x0000..=0x1FFF => {
+                self.enable_external_ram = value == 0x0A;
+            }
+            0x2000..=0x3FFF => self.switch_rom_bank(value),
+            0x4000..=0x5FFF if self.mbc == MBC::MBC1 => {
+                self.bank_high = value & 0x03;
+                self.update_mbc1_banks();
+            }
+            0x4000..=0x5FFF => self.switch_ram_bank(value),
+            0x6000..=0x7FFF if self.mbc == MBC::MBC1 => {
+                self.banking_mode = value & 0x01 != 0;
+                self.update_mbc1_banks();
+            }
+            0xA000..=0xBFFF => {
+                if self.has_external_ram && self.enable_external_ram {
+                    self.memory[address as usize] = value;
+                    self.ram_dirty |= self.battery;
+                }
+            }
+            0xE000..=0xFDFF => self.memory[echo_ram_address(address as usize)] = value,
+            0xFEA0..=0xFEFF => {}
+            0xFF00 => self.input.write_ff00(value),
+            0xFF04 => self.memory[address as usize] = 0,
+            // Only the power bit is writable. The channel bits are set by
+            // the APU.
+            0xFF26 => self.memory[NR52] = (value & 0x80) | (self.memory[NR52] & 0x7F),
+            // Only the interrupt source selects are writable. The mode and
+            // coincidence bits are set by the PPU.
+            0xFF41 => {
+                if self.stat_write_bug && self.stat_line_up() {
+                    Interrupt::LcdStat.trigger(self);
+                }
+                self.memory[STAT] = 0x80 | (value & 0x78) | (self.memory[STAT] & 0x07);
+            }
+            0xFF45 => {
+                self.memory[LYC] = value;
+                self.update_coincidence();
+            }
+            0xFF46 => {
+                self.memory[address as usize] = value;
+                self.start_dma(value);
+            }
+            0xFF4F => {
+                self.switch_vram_bank(value);
+                self.memory[VBK] = value;
+            }
+            0xFF50 => {
+                if self.boot_rom_mapped && value != 0 {
+                    self.unmap_boot_rom();
+                }
+                self.memory[address as usize] = value;
+            }
+            address => self.memory[address as usize] = value,
+        }
+    }
+
+    // The mode bits of STAT, which the PPU keeps up to date
+    pub fn ppu_mode(&self) -> u8 {
+        self.memory[STAT] & 0b11
+    }
+
+    // Move STAT to a new PPU mode
+    pub fn set_ppu_mode(&mut self, mode: u8) {
+        self.memory[STAT] = (self.memory[STAT] & !0b11) | (mode & 0b11);
+    }
+
+    pub fn set_stat_write_bug(&mut self, enabled: bool) {
+        self.stat_write_bug = enabled;
+    }
+
+    pub fn stat_write_bug(&self) -> bool {
+        self.stat_write_bug
+    }
+
+    // Whether the STAT line would be up with every source enabled, which is
+    // what a write briefly does on a DMG
+    fn stat_line_up(&self) -> bool {
+        self.memory[LCDC] & 0x80 != 0
+            && (matches!(self.ppu_mode(), 0 | 1) || self.memory[STAT] & 0b100 != 0)
+    }
+
+    pub fn init_ram(&self) -> InitRam {
+        self.init_ram
+    }
+
+    // Set STAT's coincidence bit to whether LY == LYC. Returns the new state.
+    pub fn update_coincidence(&mut self) -> bool {
+        let coincidence = self.memory[LY] == self.memory[LYC];
+        self.memory[STAT] = (self.memory[STAT] & !0b100) | if coincidence { 0b100 } else { 0 };
+        coincidence
+    }
+
+    // While the LCD is on, the PPU has VRAM to itself in mode 3 and OAM in
+    // modes 2 and 3. The CPU reads 0xFF and its writes are dropped.
+    fn blocked_by_ppu(&self, address: usize) -> bool {
+        if self.memory[LCDC] & 0x80 == 0 {
+            return false;
+        }
+        match address {
+            0x8000..=0x9FFF => self.ppu_mode() == 3,
+            0xFE00..=0xFE9F => self.ppu_mode() >= 2,
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, address: usize) -> u8 {
+        let address = address & 0xFFFF;
+        if self.blocked_by_ppu(address) {
+            0xFF
+        } else {
+            self.peek(address)
+        }
+    }
+
+    // Read without the CPU's access restrictions, for the PPU and debug views
+    pub fn peek(&self, address: usize) -> u8 {
+        let address = address & 0xFFFF;
+        match address {
+            0x0000..=0x7FFF => self
+                .cheats
+                .patch_rom_read(address as u16, self.memory[address]),
+            // Open bus when the RAM is disabled or the cartridge has none
+            0xA000..=0xBFFF => {
+                if self.has_external_ram && self.enable_external_ram {
+                    self.memory[address]
+                } else {
+                    0xFF
+                }
+            }
+            0xE000..=0xFDFF => self.memory[echo_ram_address(address)],
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00 => self.input.read_ff00(),
+            0xFF01..=0xFF7F => self.memory[address] | self.io_read_mask(address),
+            _ => self.memory[address],
+        }
+    }
+
+    // Bits of the I/O registers that always read as 1, and 0xFF for the
+    // registers that don't exist (including the CGB ones on a DMG)
+    fn io_read_mask(&self, address: usize) -> u8 {
+        match address {
+            0xFF02 => 0x7E,
+            0xFF03 | 0xFF08..=0xFF0E => 0xFF,
+            0xFF07 => 0xF8,
+            0xFF0F => 0xE0,
+            0xFF10..=0xFF26 => APU_READ_MASKS[address - 0xFF10],
+            0xFF27..=0xFF2F => 0xFF,
+            0xFF41 => 0x80,
+            KEY1 if self.cgb_mode() => 0x7E,
+            VBK if self.cgb_mode() => 0xFE,
+            0xFF4C..=0xFF7F if !self.cgb_mode() => 0xFF,
+            _ => 0x00,
+        }
+    }
+
+    // The shades OBP0 (`which` = 0) or OBP1 (1) maps sprite colors 0-3 to.
+    // Color 0 is always transparent, so its entry is never drawn.
+    pub fn object_palette(&self, which: u8) -> [u8; 4] {
+        assert!(which < 2, "Invalid object palette {which}");
+        let obp = self.get(OBP0 + which as usize);
+        [0, 1, 2, 3].map(|color| (obp >> (color * 2)) & 0b11)
+    }
+
+    // One line per OAM entry, for the debugger
+    pub fn dump_oam(&self) -> String {
+        (0..40)
+            .map(|i| {
+                let oam = read_oam(self, 0xFE00 + i * 4);
+                format!(
+                    "{:2}: Y={:02X} X={:02X} tile={:02X} flags={:02X}\n",
+                    i, oam.y, oam.x, oam.tile, oam.flags
+                )
+            })
+            .collect()
+    }
+
+    // The bytes from `start` up to `end` as mapped right now, so 0x4000-0x7FFF
+    // is whichever ROM bank is selected. Reads ignore the PPU's locks.
+    pub fn dump_range(&self, start: usize, end: usize) -> Vec<u8> {
+        (start..end.min(0x10000))
+            .map(|address| self.peek(address))
+            .collect()
+    }
+
+    // Write `bytes` from `start` on, straight into what's mapped now with no
+    // side effects, e.g. to poke VRAM in any PPU mode. Writes to a banked
+    // area don't survive switching the bank out.
+    pub fn load_range(&mut self, start: usize, bytes: &[u8]) -> Result<(), String> {
+        if start + bytes.len() > 0x10000 {
+            return Err(format!(
+                "{} bytes at {start:#06X} run past 0xFFFF",
+                bytes.len()
+            ));
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self[start + i] = byte;
+        }
+        Ok(())
+    }
+
+    // The 32x32 tile indices of map 0 (0x9800) or map 1 (0x9C00)
+    pub fn dump_tilemap(&self, which: u8) -> String {
+        let start = if which == 0 { 0x9800 } else { 0x9C00 };
+        (0..32)
+            .map(|row| {
+                let tiles: Vec<String> = (0..32)
+                    .map(|col| format!("{:02X}", self.peek(start + row * 32 + col)))
+                    .collect();
+                tiles.join(" ") + "\n"
+            })
+            .collect()
+    }
+
+    // Apply the GameShark codes, which rewrite RAM once a frame
+    pub fn apply_ram_cheats(&mut self) {
+        let writes: Vec<(u16, u8)> = self.cheats.ram_writes().collect();
+        for (address, value) in writes {
+            self.set(address, value);
+        }
+    }
+
+    pub fn handle_input_event(&mut self, event: &Event) {
+        if self.input.handle_event(event) {
+            Interrupt::Joypad.trigger(self);
+        }
+    }
+
+    // Press or release a button without an input event, requesting the
+    // joypad interrupt like a live key press would
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let event = if pressed {
+            Event::ButtonDown(button)
+        } else {
+            Event::ButtonUp(button)
+        };
+        self.handle_input_event(&event);
+    }
+
+    // Whether the cartridge header flags CGB support
+    pub fn cgb_mode(&self) -> bool {
+        self.memory[0x143] & 0x80 != 0
+    }
+
+    pub fn get_wave_ram(&self) -> &[u8] {
+        &self.memory[WAVE_RAM_START..WAVE_RAM_START + 0x10]
+    }
+
+    pub fn inc_div(&mut self) {
+        self.memory[0xFF04] = self.memory[0xFF04].wrapping_add(1);
+    }
+
+    // Writing 0xFF46 starts a DMA from page `page`, restarting from the first
+    // byte if one is already running
+    fn start_dma(&mut self, page: u8) {
+        self.dma = Some(Dma {
+            source: page as usize * 0x100,
+            copied: 0,
+            cycles: 0,
+        });
+    }
+
+    pub fn dma_active(&self) -> bool {
+        self.dma.is_some()
+    }
+
+    // Advance a running DMA by `cycles` T-cycles of the CPU
+    pub fn update_dma(&mut self, cycles: u32) {
+        let Some(mut dma) = self.dma else {
+            return;
+        };
+        dma.cycles += cycles;
+        while dma.cycles >= 4 && dma.copied < 0xA0 {
+            self.memory[0xFE00 + dma.copied] =
+                self.memory[echo_ram_address(dma.source + dma.copied)];
+            dma.copied += 1;
+            dma.cycles -= 4;
+        }
+        self.dma = (dma.copied < 0xA0).then_some(dma);
+    }
+}
+
+// Bits of NR10-NR52 (0xFF10-0xFF26) that read as 1. The frequency registers
+// are write-only, as are the lengths and the trigger bits. NR52's low four
+// bits show which channels are on.
+#[rustfmt::skip]
+const APU_READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+// Echo RAM at 0xE000-0xFDFF mirrors WRAM at 0xC000-0xDDFF
+fn echo_ram_address(address: usize) -> usize {
+    match address {
+        0xE000..=0xFDFF => address - 0x2000,
+        _ => address,
+    }
+}
+
+impl Index<usize> for Mmu {
+    type Output = u8;
+
+    // Raw access that ignores the read masks, which is how the APU sees its
+    // write-only registers
+    fn index(&self, index: usize) -> &u8 {
+        &self.memory[echo_ram_address(index & 0xFFFF)]
+    }
+}
+
+impl IndexMut<usize> for Mmu {
+    // Raw access with no write side effects. DIV is only reset by `set`.
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        let index = index & 0xFFFF;
+        &mut self.memory[echo_ram_address(index)]
+    }
+}
+
+pub fn load_rom(path: &str) -> Result<Vec<u8>, RomError> {
+    println!("Loading ROM: {}", path);
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+// What the cartridge header at 0x0134-0x014F says about a ROM
+#[derive(Debug, PartialEq, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cartridge_type: u8,
+    pub mbc: MBC,
+    // In bytes
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub battery: bool,
+    pub cgb: bool,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+// Read a ROM's header without loading it, for tools that only want to know
+// what it is. Checks the same things `Mmu::initialize_from_bytes` does, plus
+// the ROM size code, but not the checksums.
+pub fn parse_header(rom: &[u8]) -> Result<CartridgeHeader, RomError> {
+    if rom.len() < 0x150 {
+        return Err(RomError::TooSmall(rom.len()));
+    }
+    let mbc = mbc_for(rom[0x147])?;
+    let ram_size = cartridge_ram_size(rom, mbc)?;
+    // 32 KB doubled once for each step
+    let rom_size = match rom[0x148] {
+        code @ 0x00..=0x08 => 0x8000 << code,
+        code => return Err(RomError::UnsupportedRomSize(code)),
+    };
+    let cgb = rom[0x143] & 0x80 != 0;
+    // CGB games give the last byte of the title to the CGB flag
+    let title = if cgb {
+        &rom[0x134..0x143]
+    } else {
+        &rom[0x134..0x144]
+    };
+    let title = title.split(|&byte| byte == 0).next().unwrap_or_default();
+    Ok(CartridgeHeader {
+        title: String::from_utf8_lossy(title).trim_end().to_string(),
+        cartridge_type: rom[0x147],
+        mbc,
+        rom_size,
+        ram_size,
+        battery: ram_size > 0 && has_battery(rom[0x147]),
+        cgb,
+        header_checksum: rom[0x14D],
+        global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
+    })
+}
+
+// The boot ROM refuses to start a cartridge whose header checksum (0x014D)
+// doesn't match the bytes at 0x0134-0x014C. Homebrew often leaves it unset,
+// so callers may choose to only warn about it.
+pub fn check_header_checksum(rom: &[u8]) -> Result<(), RomError> {
+    if rom.len() < 0x150 {
+        return Err(RomError::TooSmall(rom.len()));
+    }
+    let expected = rom[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+    let actual = rom[0x14D];
+    if expected != actual {
+        return Err(RomError::BadChecksum { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexing_div_does_not_reset_it() {
+        let mut mmu = Mmu::init();
+        assert_eq!(mmu[0xFF04], 0xAB);
+        let div = &mut mmu[0xFF04];
+        assert_eq!(*div, 0xAB);
+        assert_eq!(mmu.get(0xFF04), 0xAB);
+        mmu.set(0xFF04, 0x12);
+        assert_eq!(mmu.get(0xFF04), 0);
+    }
+
+    #[test]
+    fn test_apu_write_only_bits_read_as_1() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xFF13, 0x42);
+        assert_eq!(mmu.get(0xFF13), 0xFF);
+        mmu.set(0xFF14, 0x87);
+        assert_eq!(mmu.get(0xFF14), 0xBF);
+        mmu.set(0xFF11, 0x81);
+        assert_eq!(mmu.get(0xFF11), 0xBF);
+        mmu.set(0xFF12, 0xF3);
+        assert_eq!(mmu.get(0xFF12), 0xF3);
+        // The APU still sees what was written
+        assert_eq!(mmu[0xFF13], 0x42);
+    }
+
+    #[test]
+    fn test_dump_and_load_range_round_trip() {
+        let mut mmu = Mmu::init();
+        let bytes: Vec<u8> = (0..0x20).collect();
+        mmu.load_range(0x8000, &bytes).unwrap();
+        assert_eq!(mmu.dump_range(0x8000, 0x8020), bytes);
+        assert!(mmu.load_range(0xFFF0, &bytes).is_err());
+
+        // 0x4000 shows the selected bank
+        let mut rom = mbc1_rom(0x10000);
+        rom[0x4000] = 0x01;
+        rom[0x8000] = 0x02;
+        mmu.initialize_from_bytes(&rom).unwrap();
+        assert_eq!(mmu.dump_range(0x4000, 0x4001), [0x01]);
+        mmu.set(0x2000, 0x02);
+        assert_eq!(mmu.dump_range(0x4000, 0x4001), [0x02]);
+    }
+
+    #[test]
+    fn test_dma_copies_over_160_m_cycles() {
+        let mut mmu = Mmu::init();
+        for i in 0..0xA0 {
+            mmu.set(0xC000 + i, i as u8);
+        }
+        mmu.set(0xFF46, 0xC0);
+        mmu.update_dma(4 * 0x9F);
+        assert!(mmu.dma_active());
+        assert_eq!(mmu.peek(0xFE9E), 0x9E);
+        assert_eq!(mmu.peek(0xFE9F), 0x00);
+        mmu.update_dma(4);
+        assert!(!mmu.dma_active());
+        assert_eq!(
+            mmu.dump_range(0xFE00, 0xFEA0),
+            mmu.dump_range(0xC000, 0xC0A0)
+        );
+    }
+
+    #[test]
+    fn test_dma_restarts_from_new_source() {
+        let mut mmu = Mmu::init();
+        for i in 0..0xA0 {
+            mmu.set(0xC000 + i, 0x11);
+            mmu.set(0xD000 + i, 0x22);
+        }
+        mmu.set(0xFF46, 0xC0);
+        mmu.update_dma(40);
+        mmu.set(0xFF46, 0xD0);
+        // The first DMA's time doesn't count toward the second
+        mmu.update_dma(640 - 4);
+        assert!(mmu.dma_active());
+        mmu.update_dma(4);
+        assert!(!mmu.dma_active());
+        assert!(mmu
+            .dump_range(0xFE00, 0xFEA0)
+            .iter()
+            .all(|&byte| byte == 0x22));
+    }
+
+    #[test]
+    fn test_object_palette_decodes_obp0_and_obp1() {
+        let mut mmu = Mmu::init();
+        mmu.set(OBP0 as u16, 0xE4);
+        mmu.set(OBP1 as u16, 0x1B);
+        assert_eq!(mmu.object_palette(0), [0, 1, 2, 3]);
+        assert_eq!(mmu.object_palette(1), [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_initialize_from_bytes() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x4000] = 0xAB;
+        let mut mmu = Mmu::init();
+        assert!(mmu.initialize_from_bytes(&rom).is_ok());
+        assert_eq!(mmu.mbc, MBC::MBC1);
+        assert_eq!(mmu.get(0x4000), 0xAB);
+    }
+
+    fn mbc1_rom(len: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; len];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x147] = 0x01;
+        rom
+    }
+
+    #[test]
+    fn test_mbc1_without_ram_reads_open_bus() {
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&mbc1_rom(0x8000)).unwrap();
+        mmu.set(0x0000, 0x0A);
+        mmu.set(0xA000, 0x42);
+        assert_eq!(mmu.get(0xA000), 0xFF);
+        assert_eq!(mmu.get(0xBFFF), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc1_external_ram_needs_enable() {
+        let mut rom = mbc1_rom(0x8000);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(0xA000, 0x42);
+        assert_eq!(mmu.get(0xA000), 0xFF);
+        mmu.set(0x0000, 0x0A);
+        mmu.set(0xA000, 0x42);
+        assert_eq!(mmu.get(0xA000), 0x42);
+        mmu.set(0x0000, 0x00);
+        assert_eq!(mmu.get(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_battery_ram_save_data_and_dirty_flag() {
+        let mut rom = mbc1_rom(0x8000);
+        rom[0x147] = 0x02;
+        rom[0x149] = 0x02;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(0x0000, 0x0A);
+        mmu.set(0xA000, 0x42);
+        assert!(!mmu.take_ram_dirty());
+        assert_eq!(mmu.save_data(), None);
+
+        rom[0x147] = 0x03;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(0xA000, 0x42);
+        assert!(!mmu.take_ram_dirty(), "RAM is disabled");
+        mmu.set(0x0000, 0x0A);
+        mmu.set(0xA001, 0x17);
+        assert!(mmu.take_ram_dirty());
+        assert!(!mmu.take_ram_dirty());
+        let save = mmu.save_data().unwrap();
+        assert_eq!((save.len(), save[0], save[1]), (0x2000, 0x00, 0x17));
+
+        let mut restored = Mmu::init();
+        restored.initialize_from_bytes(&rom).unwrap();
+        restored.load_save_data(&save).unwrap();
+        restored.set(0x0000, 0x0A);
+        assert_eq!(restored.get(0xA001), 0x17);
+        assert!(restored.load_save_data(&save[..0x100]).is_err());
+    }
+
+    #[test]
+    fn test_mbc1_multicart_selects_second_game() {
+        let mut rom = mbc1_rom(0x100000);
+        rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x40100] = 0xC3;
+        rom[0x11 * 0x4000] = 0xAB;
+        // 32 KB of RAM, so there's a bank 1 to select
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x03;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        assert!(mmu.mbc1_multicart);
+
+        mmu.set(0x6000, 0x01);
+        mmu.set(0x4000, 0x01);
+        mmu.set(0x2000, 0x01);
+        assert_eq!(mmu.get(0x0100), 0xC3);
+        assert_eq!(mmu.get(0x4000), 0xAB);
+        assert_eq!(mmu.current_rom_bank(), 0x11);
+        assert_eq!(mmu.current_ram_bank(), 0x01);
+    }
+
+    #[test]
+    fn test_mbc1_high_bits_shift_by_five() {
+        let mut rom = mbc1_rom(0x100000);
+        rom[0x22 * 0x4000] = 0xAB;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        assert!(!mmu.mbc1_multicart);
+
+        mmu.set(0x4000, 0x01);
+        mmu.set(0x2000, 0x02);
+        assert_eq!(mmu.get(0x4000), 0xAB);
+        assert_eq!(mmu.current_rom_bank(), 0x22);
+        assert_eq!(mmu.current_ram_bank(), 0x00);
+    }
+
+    #[test]
+    fn test_initialize_from_bytes_pads_short_rom() {
+        let mut rom = vec![0u8; 0x200];
+        rom[0x1FF] = 0xAB;
+        let mut mmu = Mmu::init();
+        assert!(mmu.initialize_from_bytes(&rom).is_ok());
+        assert_eq!(mmu.get(0x1FF), 0xAB);
+        assert_eq!(mmu.get(0x7FFF), 0x00);
+        assert_eq!(mmu.total_rom.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_boot_rom_unmaps_on_ff50_write() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x00] = 0xAB;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+
+        assert!(matches!(
+            mmu.load_boot_rom(&[0x31; 0x10]),
+            Err(RomError::InvalidBootRom(0x10))
+        ));
+        mmu.load_boot_rom(&[0x31; 0x100]).unwrap();
+        assert!(mmu.boot_rom_mapped());
+        assert_eq!(mmu.get(0x00), 0x31);
+
+        mmu.set(0xFF50, 0x01);
+        assert!(!mmu.boot_rom_mapped());
+        assert_eq!(mmu.get(0x00), 0xAB);
+    }
+
+    #[test]
+    fn test_joypad_interrupt_on_press() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xFF0F, 0);
+        // Select the button keys
+        mmu.set(0xFF00, 0x10);
+        mmu.handle_input_event(&Event::ButtonDown(Button::A));
+        assert_eq!(mmu.get(0xFF0F) & 0b1_0000, 0b1_0000);
+
+        mmu.set(0xFF0F, 0);
+        mmu.handle_input_event(&Event::ButtonUp(Button::A));
+        assert_eq!(mmu.get(0xFF0F) & 0b1_0000, 0);
+    }
+
+    #[test]
+    fn test_no_joypad_interrupt_for_unselected_line() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xFF0F, 0);
+        // Select the direction keys only
+        mmu.set(0xFF00, 0x20);
+        mmu.handle_input_event(&Event::ButtonDown(Button::A));
+        assert_eq!(mmu.get(0xFF0F) & 0b1_0000, 0);
+    }
+
+    #[test]
+    fn test_vram_and_oam_blocked_by_ppu_mode() {
+        let mut mmu = Mmu::init();
+        mmu.set(0x8000, 0x12);
+        mmu.set(0xFE00, 0x34);
+
+        // Mode 3 locks out both
+        mmu.memory[STAT] = 0x83;
+        assert_eq!(mmu.get(0x8000), 0xFF);
+        assert_eq!(mmu.get(0xFE00), 0xFF);
+        mmu.set(0x8000, 0x56);
+        assert_eq!(mmu.peek(0x8000), 0x12);
+
+        // Mode 2 only locks out OAM
+        mmu.memory[STAT] = 0x82;
+        assert_eq!(mmu.get(0x8000), 0x12);
+        assert_eq!(mmu.get(0xFE00), 0xFF);
+
+        // Nothing is locked with the LCD off
+        mmu.memory[STAT] = 0x83;
+        mmu.set(LCDC as u16, 0x11);
+        assert_eq!(mmu.get(0x8000), 0x12);
+        assert_eq!(mmu.get(0xFE00), 0x34);
+    }
+
+    #[test]
+    fn test_echo_ram_mirrors_wram() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xC000, 0xAB);
+        assert_eq!(mmu.get(0xE000), 0xAB);
+        assert_eq!(mmu[0xE000], 0xAB);
+
+        mmu.set(0xFDFF, 0xCD);
+        assert_eq!(mmu.get(0xDDFF), 0xCD);
+    }
+
+    #[test]
+    fn test_unusable_region() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xFEA0, 0x12);
+        assert_eq!(mmu.get(0xFEA0), 0xFF);
+        assert_eq!(mmu.get(0xFEFF), 0xFF);
+    }
+
+    #[test]
+    fn test_dump_oam_and_tilemap() {
+        let mut mmu = Mmu::init();
+        mmu.set(0xFE04, 0x10);
+        mmu.set(0xFE05, 0x08);
+        mmu.set(0xFE06, 0x2A);
+        mmu.set(0xFE07, 0x60);
+        mmu.set(0x9C21, 0x7F);
+
+        let oam = mmu.dump_oam();
+        assert_eq!(oam.lines().count(), 40);
+        assert_eq!(oam.lines().nth(1), Some(" 1: Y=10 X=08 tile=2A flags=60"));
+
+        let map = mmu.dump_tilemap(1);
+        assert_eq!(map.lines().count(), 32);
+        assert_eq!(&map.lines().nth(1).unwrap()[..5], "00 7F");
+        assert!(!mmu.dump_tilemap(0).contains("7F"));
+    }
+
+    #[test]
+    fn test_init_ram_modes() {
+        let a = Mmu::init_with_ram(InitRam::Random(42));
+        let b = Mmu::init_with_ram(InitRam::Random(42));
+        let c = Mmu::init_with_ram(InitRam::Random(43));
+        assert_eq!(a.memory[0xC000..0xE000], b.memory[0xC000..0xE000]);
+        assert_eq!(a.memory[0xFF80..0xFFFF], b.memory[0xFF80..0xFFFF]);
+        assert_ne!(a.memory[0xC000..0xE000], c.memory[0xC000..0xE000]);
+        assert!(a.memory[0xC000..0xC100].iter().any(|&byte| byte != 0));
+
+        let pattern = Mmu::init_with_ram(InitRam::Pattern);
+        assert_eq!(
+            pattern.memory[0xC000..0xC010],
+            [[0x00; 8], [0xFF; 8]].concat()
+        );
+        assert!(Mmu::init().memory[0xC000..0xE000]
+            .iter()
+            .all(|&byte| byte == 0));
+        // IE isn't RAM
+        assert_eq!(pattern.get(0xFFFF), 0x00);
+    }
+
+    #[test]
+    fn test_parse_init_ram() {
+        assert_eq!(InitRam::parse("pattern"), Ok(InitRam::Pattern));
+        assert_eq!(InitRam::parse("random:7"), Ok(InitRam::Random(7)));
+        assert_eq!(
+            InitRam::parse("random:x"),
+            Err("Invalid seed x".to_string())
+        );
+        assert!(InitRam::parse("ones").is_err());
+    }
+
+    #[test]
+    fn test_vram_bank_switch() {
+        let mut rom = mbc1_rom(0x8000);
+        rom[0x143] = 0x80;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(LCDC as u16, 0x00);
+        mmu.set(0x8000, 0x11);
+        mmu.set(VBK as u16, 0x01);
+        assert_eq!(mmu.get(VBK), 0xFF);
+        assert_eq!(mmu.get(0x8000), 0x00);
+        mmu.set(0x8000, 0x22);
+        mmu.set(0x9FFF, 0x33);
+
+        mmu.set(VBK as u16, 0x00);
+        assert_eq!(mmu.get(VBK), 0xFE);
+        assert_eq!(mmu.get(0x8000), 0x11);
+        assert_eq!(mmu.peek_vram(1, 0x8000), 0x22);
+        mmu.set(VBK as u16, 0x01);
+        assert_eq!(mmu.get(0x8000), 0x22);
+        assert_eq!(mmu.get(0x9FFF), 0x33);
+        assert_eq!(mmu.peek_vram(0, 0x8000), 0x11);
+    }
+
+    #[test]
+    fn test_vram_bank_ignored_on_dmg() {
+        let mut mmu = Mmu::init();
+        mmu.set(LCDC as u16, 0x00);
+        mmu.set(0x8000, 0x11);
+        mmu.set(VBK as u16, 0x01);
+        assert_eq!(mmu.current_vram_bank(), 0);
+        assert_eq!(mmu.get(0x8000), 0x11);
+        assert_eq!(mmu.get(VBK), 0xFF);
+    }
+
+    #[test]
+    fn test_stat_bit_7_reads_set() {
+        let mut mmu = Mmu::init();
+        mmu.set_ppu_mode(0);
+        mmu.set(STAT as u16, 0x00);
+        assert_eq!(mmu.get(STAT) & 0xF8, 0x80);
+        mmu.set(STAT as u16, 0x45);
+        assert_eq!(mmu.get(STAT) & 0xF8, 0xC0);
+    }
+
+    #[test]
+    fn test_stat_write_keeps_mode_and_coincidence_bits() {
+        let mut mmu = Mmu::init();
+        mmu.set_ppu_mode(3);
+        mmu.set(LY as u16, 0);
+        mmu.set(LYC as u16, 0);
+        mmu.set(STAT as u16, 0x00);
+        assert_eq!(mmu.get(STAT), 0x87);
+        mmu.set(STAT as u16, 0xF8);
+        assert_eq!(mmu.get(STAT), 0xFF);
+        assert_eq!(mmu.ppu_mode(), 3);
+    }
+
+    #[test]
+    fn test_stat_write_bug_raises_interrupt_outside_mode_3() {
+        let mut mmu = Mmu::init();
+        mmu.set(LCDC as u16, 0x80);
+        mmu.set(LY as u16, 1);
+        mmu.set(LYC as u16, 0);
+        mmu.set_ppu_mode(0);
+        mmu.set(0xFF0F, 0);
+        mmu.set(STAT as u16, 0x00);
+        assert_eq!(mmu.get(0xFF0F) & 0b10, 0);
+
+        mmu.set_stat_write_bug(true);
+        mmu.set(STAT as u16, 0x00);
+        assert_eq!(mmu.get(0xFF0F) & 0b10, 0b10);
+        mmu.set(0xFF0F, 0);
+        mmu.set_ppu_mode(3);
+        mmu.set(STAT as u16, 0x00);
+        assert_eq!(mmu.get(0xFF0F) & 0b10, 0);
+    }
+
+    #[test]
+    fn test_unused_io_registers_read_ff() {
+        let mut mmu = Mmu::init();
+        for address in [0xFF03, 0xFF08, 0xFF0E, 0xFF27, 0xFF4D, 0xFF7F] {
+            mmu.set(address, 0x00);
+            assert_eq!(mmu.get(address as usize), 0xFF);
+        }
+        mmu.set(0xFF0F, 0x01);
+        assert_eq!(mmu.get(0xFF0F), 0xE1);
+    }
+
+    #[test]
+    fn test_initialize_from_bytes_errors() {
+        let mut mmu = Mmu::init();
+        assert!(matches!(
+            mmu.initialize_from_bytes(&[0u8; 0x100]),
+            Err(RomError::TooSmall(0x100))
+        ));
+
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0xFC;
+        assert!(matches!(
+            mmu.initialize_from_bytes(&rom),
+            Err(RomError::UnsupportedMbc(0xFC))
+        ));
+
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x06;
+        assert!(matches!(
+            mmu.initialize_from_bytes(&rom),
+            Err(RomError::UnsupportedRamSize(0x06))
+        ));
+
+        rom[0x149] = 0x00;
+        assert!(matches!(
+            mmu.initialize_from_bytes(&rom),
+            Err(RomError::MissingRam(0x03))
+        ));
+    }
+
+    fn header_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x150];
+        rom[0x134..0x13A].copy_from_slice(b"TETRIS");
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        rom[0x14D] = 0x0A;
+        rom[0x14E..0x150].copy_from_slice(&[0x16, 0xBF]);
+        rom
+    }
+
+    #[test]
+    fn test_parse_header_for_each_mbc() {
+        let cases = [
+            (0x00, 0x00, 0x00, MBC::None, 0x8000, 0, false),
+            (0x01, 0x04, 0x00, MBC::MBC1, 0x80000, 0, false),
+            (0x03, 0x05, 0x03, MBC::MBC1, 0x100000, 0x8000, true),
+            (0x06, 0x03, 0x00, MBC::MBC2, 0x40000, 0x200, true),
+            (0x13, 0x06, 0x03, MBC::MBC3, 0x200000, 0x8000, true),
+            (0x1B, 0x08, 0x04, MBC::MBC5, 0x800000, 0x20000, true),
+        ];
+        for (cartridge_type, rom_code, ram_code, mbc, rom_size, ram_size, battery) in cases {
+            let header = parse_header(&header_rom(cartridge_type, rom_code, ram_code)).unwrap();
+            assert_eq!(header.cartridge_type, cartridge_type);
+            assert_eq!(header.mbc, mbc);
+            assert_eq!(header.rom_size, rom_size);
+            assert_eq!(header.ram_size, ram_size);
+            assert_eq!(header.battery, battery);
+        }
+
+        let header = parse_header(&header_rom(0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert!(!header.cgb);
+        assert_eq!(header.header_checksum, 0x0A);
+        assert_eq!(header.global_checksum, 0x16BF);
+    }
+
+    #[test]
+    fn test_parse_header_cgb_title_and_errors() {
+        let mut rom = header_rom(0x00, 0x00, 0x00);
+        rom[0x134..0x144].copy_from_slice(b"POKEMON CRYSTAL\x80");
+        let header = parse_header(&rom).unwrap();
+        assert!(header.cgb);
+        assert_eq!(header.title, "POKEMON CRYSTAL");
+
+        assert!(matches!(
+            parse_header(&rom[..0x14F]),
+            Err(RomError::TooSmall(0x14F))
+        ));
+        assert!(matches!(
+            parse_header(&header_rom(0xFC, 0x00, 0x00)),
+            Err(RomError::UnsupportedMbc(0xFC))
+        ));
+        assert!(matches!(
+            parse_header(&header_rom(0x00, 0x09, 0x00)),
+            Err(RomError::UnsupportedRomSize(0x09))
+        ));
+        assert!(matches!(
+            parse_header(&header_rom(0x03, 0x00, 0x00)),
+            Err(RomError::MissingRam(0x03))
+        ));
+    }
+
+    #[test]
+    fn test_mbc5_128kb_ram_bank_15() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x1B;
+        rom[0x149] = 0x04;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu.set(0x0000, 0x0A);
+
+        mmu.set(0x4000, 0x0F);
+        assert_eq!(mmu.current_ram_bank(), 15);
+        mmu.set(0xA000, 0x42);
+        mmu.set(0x4000, 0x00);
+        assert_eq!(mmu.get(0xA000), 0x00);
+        mmu.set(0x4000, 0x0F);
+        assert_eq!(mmu.get(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_check_header_checksum() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x134..0x13A].copy_from_slice(b"GUMBAL");
+        assert!(matches!(
+            check_header_checksum(&rom),
+            Err(RomError::BadChecksum {
+                expected: 0x2F,
+                actual: 0x00
+            })
+        ));
+        rom[0x14D] = 0x2F;
+        assert!(check_header_checksum(&rom).is_ok());
+    }
+}
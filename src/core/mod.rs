@@ -0,0 +1,19 @@
+// The emulator itself, with no dependency on SDL. Frontends plug in through
+// the traits in `frontend`.
+#[cfg(feature = "apng")]
+pub mod apng;
+pub mod apu;
+pub mod cheats;
+pub mod cpu;
+pub mod emulator;
+pub mod frontend;
+pub mod input;
+pub mod interrupts;
+pub mod mmu;
+pub mod ppu;
+pub mod registers;
+pub mod replay;
+pub mod serial;
+pub mod symbols;
+#[cfg(test)]
+pub(crate) mod test_support;
@@ -0,0 +1,1280 @@
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::core::frontend::Renderer;
+use crate::core::interrupts::Interrupt;
+use crate::core::mmu::Mmu;
+use crate::core::registers::*;
+
+// Clock speed in Hz
+const CLOCK_SPEED: u32 = 4_194_304;
+// Duration of a frame in microseconds
+const FRAME_DURATION: u32 = 16_743;
+const PIXEL_BUFFER_SIZE: usize = 176 * 176 * 3;
+
+// An RGB screen color
+pub type Color = (u8, u8, u8);
+pub type Colors = [Color; 4];
+
+const GREEN_PALETTE: Colors = [
+    (0x8c, 0xb5, 0x28),
+    (0x6c, 0x94, 0x21),
+    (0x42, 0x6b, 0x29),
+    (0x21, 0x42, 0x31),
+];
+const GRAY_PALETTE: Colors = [
+    (0xff, 0xff, 0xff),
+    (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55),
+    (0x00, 0x00, 0x00),
+];
+const POCKET_PALETTE: Colors = [
+    (0xc4, 0xcf, 0xa1),
+    (0x8b, 0x95, 0x6d),
+    (0x4d, 0x53, 0x3c),
+    (0x1f, 0x1f, 0x1f),
+];
+
+// The curated palette presets: the original DMG's green, plain grayscale for
+// debugging, and the Game Boy Pocket's screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    #[default]
+    DmgGreen,
+    Grayscale,
+    GbPocket,
+}
+
+impl RenderMode {
+    // green, gray (or grey) or pocket
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "green" => Ok(RenderMode::DmgGreen),
+            "gray" | "grey" => Ok(RenderMode::Grayscale),
+            "pocket" => Ok(RenderMode::GbPocket),
+            _ => Err(format!("Expected green, gray or pocket, got {name}")),
+        }
+    }
+
+    pub fn colors(self) -> Colors {
+        match self {
+            RenderMode::DmgGreen => GREEN_PALETTE,
+            RenderMode::Grayscale => GRAY_PALETTE,
+            RenderMode::GbPocket => POCKET_PALETTE,
+        }
+    }
+}
+
+// Parse a palette preset name (green, gray, pocket) or a comma-separated list
+// of four RGB hex colors from lightest to darkest, e.g. e0f8d0,88c070,346856,081820
+pub fn parse_palette(name: &str) -> Result<Colors, String> {
+    if let Ok(mode) = RenderMode::parse(name) {
+        return Ok(mode.colors());
+    }
+    let colors = name
+        .split(',')
+        .map(|hex| {
+            let hex = hex.trim().trim_start_matches('#');
+            let value = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or(format!("Invalid color {hex}"))?;
+            Ok(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    colors
+        .try_into()
+        .map_err(|_| format!("Expected green, gray, pocket or four hex colors, got {name}"))
+}
+
+// Look up the screen color of a 2-bit color index through a palette register
+pub fn palette_color(colors: &Colors, palette: u8, color: u8) -> Color {
+    colors[(palette >> (color * 2)) as usize & 0b11]
+}
+
+// Get the value of a bit in a number
+fn get_bit<T>(value: T, bit: u32) -> T
+where
+    T: Into<u16> + From<u8>,
+{
+    let bits: u16 = value.into();
+    let result = bits & (0b1 << bit) != 0;
+
+    if result {
+        T::from(1u8)
+    } else {
+        T::from(0u8)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Palette {
+    OBP0,
+    OBP1,
+    BGP,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    color: u8,
+    palette: Palette,
+    priority: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OAM {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+pub fn read_oam(mem: &Mmu, address: usize) -> OAM {
+    OAM {
+        y: mem.peek(address),
+        x: mem.peek(address + 1),
+        tile: mem.peek(address + 2),
+        flags: mem.peek(address + 3),
+    }
+}
+
+// Pick the sprite that should be drawn at `lx`. Lower X wins, and for sprites
+// with equal X the one earlier in OAM (and so in the buffer) has priority.
+fn select_sprite(sprite_buffer: &[OAM], lx: u8) -> Option<usize> {
+    sprite_buffer
+        .iter()
+        .enumerate()
+        .filter(|(_, sprite)| lx >= sprite.x && lx - sprite.x < 8)
+        .min_by_key(|(index, sprite)| (sprite.x, *index))
+        .map(|(index, _)| index)
+}
+
+// Decide which of the BG and OBJ pixels ends up on screen:
+// - OBJ disabled (LCDC bit 1): always BG
+// - BG disabled (LCDC bit 0): BG is blank (color 0), so any opaque OBJ wins
+// - Otherwise an opaque OBJ wins unless its priority flag is set and the BG
+//   color is 1-3. OBJ color 0 is always transparent.
+fn merge_pixels(lcdc: u8, bg: Pixel, sprite: Option<Pixel>) -> Pixel {
+    let bg = if get_bit(lcdc, 0) == 0 {
+        Pixel { color: 0, ..bg }
+    } else {
+        bg
+    };
+    match sprite {
+        Some(sprite)
+            if get_bit(lcdc, 1) != 0
+                && sprite.color != 0
+                && !(sprite.priority && bg.color != 0) =>
+        {
+            sprite
+        }
+        _ => bg,
+    }
+}
+
+// Mode 3 takes 172 cycles plus the line's SCX, window and sprite penalties,
+// but never more than 289, which leaves 87 cycles of HBlank
+fn mode3_length(penalty: i32) -> i32 {
+    (172 + penalty).clamp(172, 289)
+}
+
+// WX is offset by 7 (WX = 7 puts the window at screen X 0), and `lx` runs 8
+// pixels ahead of the screen X, so the window starts at lx = WX + 1. For
+// WX < 7 this lands inside the discarded first 8 pixels, which leaves the
+// window partially off-screen to the left.
+fn window_start(wx: u8) -> u16 {
+    wx as u16 + 1
+}
+
+fn window_active(mem: &Mmu, lx: u8) -> bool {
+    get_bit(mem.get(LCDC), 5) != 0
+        && lx as u16 >= window_start(mem.get(WX))
+        && mem.get(LY) >= mem.get(WY)
+}
+
+// A layer that can be hidden for debugging, whatever LCDC says
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Layer {
+    Background,
+    Window,
+    Objects,
+}
+
+// Discriminants match the mode bits in STAT
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum PPUMode {
+    HBlank = 0,
+    VBlank = 1,
+    OAMSearch = 2,
+    PixelTransfer = 3,
+}
+
+pub struct PPU {
+    bg_fifo: VecDeque<Pixel>,
+    sprite_fifo: VecDeque<Pixel>,
+    sprite_buffer: Vec<OAM>,
+    mode: PPUMode,
+    clock_cycles: u32,
+    start_time: Instant,
+    pixel_buffer: [u8; PIXEL_BUFFER_SIZE],
+    lx: u8,
+    window_counter: u8,
+    fetching_window: bool,
+    // Whether a visible window pixel was drawn on this line. The window's
+    // line counter only advances on lines where it was.
+    window_drawn_this_line: bool,
+    tall_sprites: bool,
+    cycle_counter: i32,
+    mode3_length: i32,
+    palette: Colors,
+    // Frames to skip after each one that's drawn, and how many of them are
+    // left. A skipped frame isn't drawn into the framebuffer or presented.
+    frame_skip: u32,
+    frames_to_skip: u32,
+    // Whether any enabled STAT source was active at the last check. The
+    // sources are ORed into one line and the interrupt fires on its rising
+    // edge, so it fires once per event rather than on every check.
+    stat_line: bool,
+    // Debug overrides that blank a layer on top of the LCDC enable bits. The
+    // fetcher still runs, so timing is the same as with the layer shown.
+    pub force_bg_off: bool,
+    pub force_window_off: bool,
+    pub force_obj_off: bool,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            bg_fifo: VecDeque::new(),
+            sprite_fifo: VecDeque::new(),
+            sprite_buffer: Vec::new(),
+            mode: PPUMode::OAMSearch,
+            clock_cycles: 0,
+            start_time: Instant::now(),
+            pixel_buffer: [0; PIXEL_BUFFER_SIZE],
+            lx: 0,
+            window_counter: 0,
+            fetching_window: false,
+            window_drawn_this_line: false,
+            tall_sprites: false,
+            cycle_counter: 0,
+            mode3_length: 0,
+            palette: GREEN_PALETTE,
+            frame_skip: 0,
+            frames_to_skip: 0,
+            stat_line: false,
+            force_bg_off: false,
+            force_window_off: false,
+            force_obj_off: false,
+        }
+    }
+
+    // Back to the start of a frame with empty FIFOs, keeping the palette,
+    // frame skip and layer overrides
+    pub fn reset(&mut self) {
+        *self = PPU {
+            palette: self.palette,
+            frame_skip: self.frame_skip,
+            force_bg_off: self.force_bg_off,
+            force_window_off: self.force_window_off,
+            force_obj_off: self.force_obj_off,
+            ..PPU::new()
+        };
+    }
+
+    // Draw only one frame in every `frame_skip + 1`. The fetcher still runs
+    // on skipped frames so mode 3, and with it STAT and the interrupts, takes
+    // exactly as long; only the pixel output and presenting are skipped.
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.frame_skip = frame_skip;
+        self.frames_to_skip = 0;
+    }
+
+    pub fn set_palette(&mut self, colors: Colors) {
+        self.palette = colors;
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.set_palette(mode.colors());
+    }
+
+    pub fn toggle_layer(&mut self, layer: Layer) {
+        let forced_off = match layer {
+            Layer::Background => &mut self.force_bg_off,
+            Layer::Window => &mut self.force_window_off,
+            Layer::Objects => &mut self.force_obj_off,
+        };
+        *forced_off = !*forced_off;
+    }
+
+    pub fn palette(&self) -> &Colors {
+        &self.palette
+    }
+
+    // The visible 160x144 RGB24 pixels of the last frame
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.pixel_buffer[..160 * 144 * 3]
+    }
+
+    // Return true if a frame has been rendered. Finished frames are handed to
+    // `renderer` at the start of VBlank.
+    pub fn render(
+        &mut self,
+        mem: &mut Mmu,
+        cycles: i32,
+        renderer: &mut dyn Renderer,
+    ) -> Result<bool, String> {
+        self.cycle_counter += cycles;
+        self.stat_interrupt(mem);
+        match self.mode {
+            PPUMode::VBlank => {
+                if self.cycle_counter >= 456 {
+                    self.cycle_counter -= 456;
+                    self.clock_cycles -= 456;
+                    mem.set(LY as u16, 0);
+                    Interrupt::VBlank.trigger(mem);
+                    self.window_counter = 0;
+                    if self.frames_to_skip == 0 {
+                        renderer.render_frame(&self.pixel_buffer)?;
+                        self.frames_to_skip = self.frame_skip;
+                    } else {
+                        self.frames_to_skip -= 1;
+                    }
+                    self.set_mode(mem, PPUMode::OAMSearch);
+                    return Ok(true);
+                }
+            }
+            PPUMode::OAMSearch => {
+                if self.cycle_counter >= 80 {
+                    self.tall_sprites = get_bit(mem.get(LCDC), 2) != 0;
+                    self.cycle_counter -= 80;
+                    self.scan_sprites(mem);
+                    self.set_mode(mem, PPUMode::PixelTransfer);
+                }
+            }
+            PPUMode::PixelTransfer => {
+                // The line is drawn once the shortest mode 3 has passed, and
+                // its penalties decide how much longer the mode lasts
+                if self.mode3_length == 0 && self.cycle_counter >= 172 {
+                    self.mode3_length = mode3_length(self.draw_line(mem)?);
+                }
+                if self.mode3_length != 0 && self.cycle_counter >= self.mode3_length {
+                    self.cycle_counter -= self.mode3_length;
+                    self.set_mode(mem, PPUMode::HBlank);
+                }
+            }
+            PPUMode::HBlank => {
+                let hblank_length = 456 - 80 - self.mode3_length;
+                if self.cycle_counter >= hblank_length {
+                    self.cycle_counter -= hblank_length;
+                    self.mode3_length = 0;
+                    self.lx = 0;
+                    if self.window_drawn_this_line {
+                        self.window_counter += 1;
+                    }
+                    if mem.get(LY) == 176 {
+                        mem.set(LY as u16, 0);
+                        self.set_mode(mem, PPUMode::VBlank);
+                    } else {
+                        mem.set(LY as u16, mem.get(LY) + 1);
+                        self.set_mode(mem, PPUMode::OAMSearch);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn set_mode(&mut self, mem: &mut Mmu, mode: PPUMode) {
+        self.mode = mode;
+        mem.set_ppu_mode(mode as u8);
+    }
+
+    fn wait(&mut self, cycles: u32) {
+        self.clock_cycles += cycles;
+    }
+
+    fn stat_interrupt(&mut self, mem: &mut Mmu) {
+        let stat = mem.get(STAT);
+        let mode = stat & 0b11;
+        let coincidence = mem.update_coincidence();
+        let stat_line = (coincidence && get_bit(stat, 6) != 0)
+            || (mode == 0 && get_bit(stat, 3) != 0)
+            || (mode == 1 && get_bit(stat, 4) != 0)
+            || (mode == 2 && get_bit(stat, 5) != 0);
+        if stat_line && !self.stat_line {
+            Interrupt::LcdStat.trigger(mem);
+        }
+        self.stat_line = stat_line;
+    }
+
+    // Buffer the first 10 sprites in OAM order that cover this line. OAM Y is
+    // the screen line plus 16, so sprites with Y below 16 are cut off at the
+    // top of the screen.
+    fn scan_sprites(&mut self, mem: &Mmu) {
+        let mut result = Vec::new();
+        let line = mem.get(LY) as u16 + 16;
+        for i in 0..40 {
+            let sprite_height = if self.tall_sprites { 16 } else { 8 };
+            let oam = read_oam(mem, 0xFE00 + i * 4);
+            // X isn't checked, so sprites off the left or right edge still
+            // use up the line's 10
+            if line >= oam.y as u16 && line < oam.y as u16 + sprite_height && result.len() < 10 {
+                result.push(oam);
+            }
+        }
+        self.sprite_buffer = result;
+        self.clock_cycles += 80;
+    }
+
+    // Tiles and maps come from VRAM bank 0 until CGB rendering exists
+    fn fetch_byte(&mut self, mem: &Mmu, addr: u16) -> u8 {
+        self.clock_cycles += 2;
+        mem.peek_vram(0, addr as usize)
+    }
+
+    fn fetch_bg(&mut self, mem: &Mmu) {
+        // The FIFO's front pixel is at background x LX + SCX - 8 (LX counts
+        // from the 8 off-screen pixels), and the fetched row lands after
+        // whatever is already queued
+        let bg_x = self
+            .lx
+            .wrapping_add(self.bg_fifo.len() as u8)
+            .wrapping_add(mem.get(SCX))
+            .wrapping_sub(8);
+        let tile_id_addr = 0x9800
+            | (get_bit(mem.get(LCDC), 3) as u16) << 10
+            | (mem.get(LY).wrapping_add(mem.get(SCY)) as u16 >> 3) << 5
+            | bg_x as u16 >> 3;
+        let tile_id = mem.peek_vram(0, tile_id_addr as usize);
+        let b12 = u16::from(!((mem.get(LCDC) & 0x10) != 0 || (tile_id & 0x80) != 0));
+        let addr = 0x8000
+            | b12 << 12
+            | (tile_id as u16) << 4
+            | ((mem.get(LY).wrapping_add(mem.get(SCY)) & 0b111) as u16) << 1;
+        let low = self.fetch_byte(mem, addr);
+        let high = self.fetch_byte(mem, addr + 1);
+        self.push_bg_tile_row(low, high);
+    }
+
+    fn push_bg_tile_row(&mut self, low: u8, high: u8) {
+        self.clock_cycles += 1;
+        for i in 0..8 {
+            let color = ((low >> (7 - i) & 0b1) << 1) | (high >> (7 - i) & 0b1);
+            self.bg_fifo.push_back(Pixel {
+                color,
+                palette: Palette::BGP,
+                priority: false,
+            });
+        }
+    }
+
+    fn fetch_window(&mut self, mem: &Mmu) {
+        // The fetched row lands after whatever is already queued in the FIFO
+        let window_x = (self.lx as usize + self.bg_fifo.len())
+            .saturating_sub(window_start(mem.get(WX)) as usize);
+        let tile_id_addr = 0x9800
+            | (get_bit(mem.get(LCDC), 6) as u16) << 10
+            | (self.window_counter as u16 >> 3) << 5
+            | (window_x as u16 >> 3) & 0x1F;
+        let tile_id = mem.peek_vram(0, tile_id_addr as usize);
+        let b12 = u16::from(!((mem.get(LCDC) & 0x10) != 0 || (tile_id & 0x80) != 0));
+        let addr: u16 = 0x8000
+            | b12 << 12
+            | (tile_id as u16) << 4
+            | ((self.window_counter & 0b111) as u16) << 1;
+        let low = self.fetch_byte(mem, addr);
+        let high = self.fetch_byte(mem, addr + 1);
+        self.push_bg_tile_row(low, high);
+    }
+
+    // Returns true if a sprite was fetched, which stalls the pixel output
+    fn fetch_obj(&mut self, mem: &Mmu) -> bool {
+        let Some(index) = select_sprite(&self.sprite_buffer, self.lx) else {
+            return false;
+        };
+        let sprite = self.sprite_buffer[index];
+        match sprite.x {
+            // Past the right edge, after the line has finished
+            168.. => false,
+            // Entirely left of the screen. It's still fetched, taking as long
+            // as any other, but there's nothing to draw.
+            0 => {
+                let transparent = Pixel {
+                    color: 0,
+                    palette: Palette::OBP0,
+                    priority: false,
+                };
+                self.sprite_fifo.extend([transparent; 8]);
+                true
+            }
+            _ => {
+                self.push_sprite_tile_row(mem, &sprite);
+                true
+            }
+        }
+    }
+
+    fn push_sprite_tile_row(&mut self, mem: &Mmu, sprite: &OAM) {
+        let hflip = get_bit(sprite.flags, 5) != 0;
+        let vflip = get_bit(sprite.flags, 6) != 0;
+        // Tall sprites flip across all 16 rows, so the flipped row also
+        // decides which of the two tiles to read
+        let height = if self.tall_sprites { 16 } else { 8 };
+        let y = mem.get(LY).wrapping_add(16).wrapping_sub(sprite.y) & (height - 1);
+        let y = if vflip { height - 1 - y } else { y };
+        let tile = if self.tall_sprites {
+            (sprite.tile & 0xFE) | (y >> 3)
+        } else {
+            sprite.tile
+        };
+        let addr = 0x8000 | (tile as u16) << 4 | ((y & 0x7) as u16) << 1;
+        let low = mem.peek_vram(0, addr as usize);
+        let high = mem.peek_vram(0, addr as usize + 1);
+        for i in (self.lx - sprite.x)..8 {
+            let x = if hflip { 7 - i } else { i };
+            self.sprite_fifo.push_back(Pixel {
+                color: ((low >> (7 - x)) & 0b1) | (((high >> (7 - x)) & 0b1) << 1),
+                palette: if get_bit(sprite.flags, 4) == 0 {
+                    Palette::OBP0
+                } else {
+                    Palette::OBP1
+                },
+                priority: get_bit(sprite.flags, 7) != 0,
+            });
+        }
+    }
+
+    fn draw_pixel(&mut self, mem: &Mmu, tile_offset: u32) -> Result<i32, String> {
+        let window_active = window_active(mem, self.lx);
+        let mut clock_cycles: i32 = 0;
+        // If we just reached the window, throw away the queued background
+        // pixels and delay the fetch by 6 cycles
+        if window_active && !self.fetching_window {
+            self.fetching_window = true;
+            self.bg_fifo.clear();
+            clock_cycles += 6;
+        }
+
+        if self.bg_fifo.len() >= 8 {
+            if self.sprite_fifo.is_empty() {
+                if self.fetch_obj(mem) {
+                    // Following the OBJ penalty algorithm from the pandocs
+                    if self.lx == 0 {
+                        clock_cycles += 11;
+                    } else {
+                        clock_cycles += 6 + max(5 - self.lx as i32 - tile_offset as i32, 0);
+                    }
+                }
+            }
+            let mut bg_pixel = self.bg_fifo.pop_front().unwrap();
+            let sprite_pixel = self.sprite_fifo.pop_front();
+            // Once the window starts, everything left in the BG FIFO is window
+            let layer_off = if self.fetching_window {
+                self.force_window_off
+            } else {
+                self.force_bg_off
+            };
+            if layer_off {
+                bg_pixel.color = 0;
+            }
+            let mut lcdc = mem.get(LCDC);
+            if self.force_obj_off {
+                lcdc &= !0b10;
+            }
+            let pixel = merge_pixels(lcdc, bg_pixel, sprite_pixel);
+            if self.fetching_window && (8..168).contains(&self.lx) {
+                self.window_drawn_this_line = true;
+            }
+            self.render_pixel(mem, pixel)?;
+            self.lx += 1;
+        } else if window_active {
+            self.fetch_window(mem);
+        } else {
+            self.fetch_bg(mem);
+        }
+        Ok(clock_cycles)
+    }
+
+    // Draw the current line, returning the cycles it adds to mode 3
+    pub fn draw_line(&mut self, mem: &Mmu) -> Result<i32, String> {
+        self.bg_fifo.clear();
+        self.sprite_fifo.clear();
+        self.lx = 0;
+        self.fetching_window = false;
+        self.window_drawn_this_line = false;
+
+        // Fine scrolling: the first tile is fetched whole and its first
+        // SCX & 7 pixels are thrown away, a cycle each
+        let tile_offset = mem.get(SCX) as u32 % 8;
+        self.fetch_bg(mem);
+        self.bg_fifo.drain(..tile_offset as usize);
+        let mut clock_cycles = tile_offset as i32;
+        self.clock_cycles += tile_offset;
+
+        while self.lx < 176 {
+            clock_cycles += self.draw_pixel(mem, tile_offset)?;
+        }
+        Ok(clock_cycles)
+    }
+
+    fn render_pixel(&mut self, mem: &Mmu, pixel: Pixel) -> Result<(), String> {
+        if self.frames_to_skip == 0 && self.lx >= 8 && mem.get(LY) < 144 {
+            let palette = match pixel.palette {
+                Palette::BGP => mem.get(BGP),
+                Palette::OBP0 => mem.get(OBP0),
+                Palette::OBP1 => mem.get(OBP1),
+            };
+            let (r, g, b) = palette_color(&self.palette, palette, pixel.color);
+            let offset = (mem.get(LY) as usize * 160 + self.lx as usize - 8) * 3;
+            self.pixel_buffer[offset] = r;
+            self.pixel_buffer[offset + 1] = g;
+            self.pixel_buffer[offset + 2] = b;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::test_support::{solid_tile, MmuBuilder};
+
+    struct CountingRenderer {
+        frames: usize,
+    }
+
+    impl Renderer for CountingRenderer {
+        fn render_frame(&mut self, pixels: &[u8]) -> Result<(), String> {
+            assert!(pixels.len() >= 160 * 144 * 3);
+            self.frames += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_render_presents_frame_at_vblank() {
+        let mut mem = Mmu::init();
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        let mut presented = false;
+        for _ in 0..100_000 {
+            if ppu.render(&mut mem, 4, &mut renderer).unwrap() {
+                presented = true;
+                break;
+            }
+        }
+        assert!(presented);
+        assert_eq!(renderer.frames, 1);
+    }
+
+    #[test]
+    fn test_line_with_ten_sprites_takes_456_cycles() {
+        let mut mem = Mmu::init();
+        for i in 0..10 {
+            let address = 0xFE00 + i * 4;
+            mem.set(address, 16);
+            mem.set(address + 1, 8 + i as u8 * 12);
+        }
+        mem.set(LCDC as u16, 0x93);
+        mem.set(LY as u16, 0);
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+
+        // Run to the start of line 1, then time it a cycle at a time
+        while mem.get(LY) != 1 {
+            ppu.render(&mut mem, 1, &mut renderer).unwrap();
+        }
+        let mut cycles = 0;
+        let mut mode3_length = 0;
+        while mem.get(LY) == 1 {
+            ppu.render(&mut mem, 1, &mut renderer).unwrap();
+            cycles += 1;
+            mode3_length = mode3_length.max(ppu.mode3_length);
+        }
+        assert_eq!(cycles, 456);
+        assert!(mode3_length > 172 && mode3_length <= 289);
+    }
+
+    #[test]
+    fn test_scx_discards_first_pixels() {
+        let mut mem = Mmu::init();
+        // Tile 0's top row has colors 0, 0, 1, 1, 2, 2, 3, 3 and tile 1's is
+        // solid color 3
+        mem.set(0x8000, 0x0F);
+        mem.set(0x8001, 0x33);
+        mem.set(0x8010, 0xFF);
+        mem.set(0x8011, 0xFF);
+        mem.set(0x9801, 1);
+        mem.set(LCDC as u16, 0x91);
+        mem.set(BGP as u16, 0xE4);
+        mem.set(SCX as u16, 3);
+        mem.set(LY as u16, 0);
+        let mut ppu = PPU::new();
+        assert_eq!(ppu.draw_line(&mem).unwrap(), 3);
+
+        let expected = [1, 2, 2, 3, 3, 3, 3, 3].map(|c| GREEN_PALETTE[c]);
+        let line: Vec<Color> = ppu.framebuffer()[..8 * 3]
+            .chunks(3)
+            .map(|p| (p[0], p[1], p[2]))
+            .collect();
+        assert_eq!(line, expected);
+    }
+
+    #[test]
+    fn test_mode3_length_is_clamped() {
+        assert_eq!(mode3_length(0), 172);
+        assert_eq!(mode3_length(40), 212);
+        assert_eq!(mode3_length(500), 289);
+    }
+
+    #[test]
+    fn test_stat_mode_bits_follow_scanline() {
+        let mut mem = Mmu::init();
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        while ppu.mode == PPUMode::OAMSearch {
+            ppu.render(&mut mem, 4, &mut renderer).unwrap();
+        }
+
+        let mut phases = vec![ppu.mode];
+        while phases.len() < 4 {
+            ppu.render(&mut mem, 4, &mut renderer).unwrap();
+            assert_eq!(mem.get(STAT) & 0b11, ppu.mode as u8);
+            if phases.last() != Some(&ppu.mode) {
+                phases.push(ppu.mode);
+            }
+        }
+        assert_eq!(
+            phases,
+            vec![
+                PPUMode::PixelTransfer,
+                PPUMode::HBlank,
+                PPUMode::OAMSearch,
+                PPUMode::PixelTransfer
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lyc_interrupt_fires_once_per_match() {
+        let mut mem = Mmu::init();
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        mem.set(LY as u16, 0);
+        mem.set(STAT as u16, 0x40);
+        mem.set(LYC as u16, 10);
+        mem.set(0xFF0F, 0);
+
+        let mut triggers = 0;
+        while mem.get(LY) != 12 {
+            ppu.render(&mut mem, 4, &mut renderer).unwrap();
+            if mem.get(0xFF0F) & 0b10 != 0 {
+                triggers += 1;
+                mem.set(0xFF0F, 0);
+            }
+        }
+        assert_eq!(triggers, 1);
+    }
+
+    #[test]
+    fn test_vblank_stat_source_fires_once_with_vblank() {
+        let mut mem = Mmu::init();
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        mem.set(LY as u16, 0);
+        mem.set_ppu_mode(2);
+        mem.set(STAT as u16, 0x10);
+        mem.set(0xFF0F, 0);
+
+        let (mut vblanks, mut stats) = (0, 0);
+        while renderer.frames == 0 {
+            ppu.render(&mut mem, 4, &mut renderer).unwrap();
+            let if_ = mem.get(0xFF0F);
+            vblanks += (if_ & 0b01 != 0) as u32;
+            stats += (if_ & 0b10 != 0) as u32;
+            mem.set(0xFF0F, 0);
+        }
+        assert_eq!(vblanks, 1);
+        assert_eq!(stats, 1);
+    }
+
+    #[test]
+    fn test_hblank_stat_source_fires_once_per_line() {
+        let mut mem = Mmu::init();
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        mem.set(LY as u16, 0);
+        // Mode 2, where a new PPU starts
+        mem.set_ppu_mode(2);
+        mem.set(STAT as u16, 0x08);
+        mem.set(0xFF0F, 0);
+
+        let mut stats = 0;
+        while mem.get(LY) != 10 {
+            ppu.render(&mut mem, 4, &mut renderer).unwrap();
+            stats += (mem.get(0xFF0F) & 0b10 != 0) as u32;
+            mem.set(0xFF0F, 0);
+        }
+        assert_eq!(stats, 10);
+    }
+
+    #[test]
+    fn test_lyc_write_updates_coincidence_flag() {
+        let mut mem = Mmu::init();
+        mem.set(LY as u16, 5);
+        mem.set(LYC as u16, 5);
+        assert_eq!(mem.get(STAT) & 0b100, 0b100);
+        mem.set(LYC as u16, 6);
+        assert_eq!(mem.get(STAT) & 0b100, 0);
+    }
+
+    fn bg_pixel(color: u8) -> Pixel {
+        Pixel {
+            color,
+            palette: Palette::BGP,
+            priority: false,
+        }
+    }
+
+    fn obj_pixel(color: u8, priority: bool) -> Pixel {
+        Pixel {
+            color,
+            palette: Palette::OBP0,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_merge_pixels_obj_disabled() {
+        let merged = merge_pixels(0b0000_0001, bg_pixel(2), Some(obj_pixel(3, false)));
+        assert_eq!(merged.color, 2);
+        assert!(matches!(merged.palette, Palette::BGP));
+    }
+
+    #[test]
+    fn test_merge_pixels_bg_disabled() {
+        let merged = merge_pixels(0b0000_0010, bg_pixel(2), Some(obj_pixel(3, true)));
+        assert_eq!(merged.color, 3);
+        assert!(matches!(merged.palette, Palette::OBP0));
+
+        let merged = merge_pixels(0b0000_0010, bg_pixel(2), Some(obj_pixel(0, false)));
+        assert_eq!(merged.color, 0);
+        assert!(matches!(merged.palette, Palette::BGP));
+    }
+
+    #[test]
+    fn test_merge_pixels_priority_behind_bg() {
+        let merged = merge_pixels(0b0000_0011, bg_pixel(1), Some(obj_pixel(3, true)));
+        assert_eq!(merged.color, 1);
+        assert!(matches!(merged.palette, Palette::BGP));
+
+        let merged = merge_pixels(0b0000_0011, bg_pixel(0), Some(obj_pixel(3, true)));
+        assert_eq!(merged.color, 3);
+        assert!(matches!(merged.palette, Palette::OBP0));
+    }
+
+    #[test]
+    fn test_merge_pixels_no_priority() {
+        let merged = merge_pixels(0b0000_0011, bg_pixel(2), Some(obj_pixel(1, false)));
+        assert_eq!(merged.color, 1);
+        assert!(matches!(merged.palette, Palette::OBP0));
+
+        let merged = merge_pixels(0b0000_0011, bg_pixel(2), Some(obj_pixel(0, false)));
+        assert_eq!(merged.color, 2);
+        assert!(matches!(merged.palette, Palette::BGP));
+    }
+
+    #[test]
+    fn test_render_mode_changes_color_3() {
+        let mut mem = Mmu::init();
+        // Tile 0 is solid color 3
+        for row in 0..16 {
+            mem.set(0x8000 + row, 0xFF);
+        }
+        mem.set(LCDC as u16, 0x91);
+        mem.set(BGP as u16, 0xE4);
+        mem.set(LY as u16, 0);
+        let mut ppu = PPU::new();
+        let pixel = |ppu: &mut PPU| {
+            ppu.draw_line(&mem).unwrap();
+            let pixel = &ppu.framebuffer()[..3];
+            (pixel[0], pixel[1], pixel[2])
+        };
+
+        assert_eq!(pixel(&mut ppu), (0x21, 0x42, 0x31));
+        ppu.set_render_mode(RenderMode::Grayscale);
+        assert_eq!(pixel(&mut ppu), (0x00, 0x00, 0x00));
+        ppu.set_render_mode(RenderMode::parse("pocket").unwrap());
+        assert_eq!(pixel(&mut ppu), (0x1f, 0x1f, 0x1f));
+        assert!(RenderMode::parse("sepia").is_err());
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(parse_palette("gray"), Ok(GRAY_PALETTE));
+        assert_eq!(
+            parse_palette("e0f8d0,88c070,#346856,081820"),
+            Ok([
+                (0xe0, 0xf8, 0xd0),
+                (0x88, 0xc0, 0x70),
+                (0x34, 0x68, 0x56),
+                (0x08, 0x18, 0x20)
+            ])
+        );
+        assert!(parse_palette("e0f8d0,88c070").is_err());
+        assert!(parse_palette("e0f8d0,88c070,346856,08182").is_err());
+        assert!(parse_palette("purple").is_err());
+    }
+
+    #[test]
+    fn test_window_at_wx_7_covers_line() {
+        let mut mem = Mmu::init();
+        mem.set(LCDC as u16, 0b1010_0001);
+        mem.set(WX as u16, 7);
+        mem.set(WY as u16, 0);
+        mem.set(LY as u16, 0);
+        assert!((8..168).all(|lx| window_active(&mem, lx)));
+        assert!((0..8).all(|lx| !window_active(&mem, lx)));
+    }
+
+    #[test]
+    fn test_window_with_small_wx_starts_off_screen() {
+        let mut mem = Mmu::init();
+        mem.set(LCDC as u16, 0b1010_0001);
+        mem.set(WX as u16, 0);
+        mem.set(WY as u16, 0);
+        mem.set(LY as u16, 0);
+        assert!(window_active(&mem, 1));
+        assert!(!window_active(&mem, 0));
+    }
+
+    // The screen Xs below 24 drawn in color 3 on a line where the BG is color
+    // 0 and only the leftmost pixel of each window tile's top row is set
+    fn window_tile_starts(wx: u8) -> Vec<usize> {
+        let mut builder = MmuBuilder::new()
+            .tile(1, [0x80, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .reg(LCDC, 0xF1)
+            .reg(BGP, 0xE4)
+            .reg(WX, wx)
+            .reg(WY, 0)
+            .reg(LY, 0);
+        for x in 0..32 {
+            builder = builder.tilemap(1, x, 0, 1);
+        }
+        let mut ppu = PPU::new();
+        ppu.draw_line(&builder.build()).unwrap();
+        (0..24)
+            .filter(|x| {
+                let p = &ppu.framebuffer()[x * 3..x * 3 + 3];
+                (p[0], p[1], p[2]) == GREEN_PALETTE[3]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_window_at_wx_7_draws_from_screen_x_0() {
+        assert_eq!(window_tile_starts(7), [0, 8, 16]);
+    }
+
+    #[test]
+    fn test_window_with_small_wx_draws_from_screen_x_0() {
+        // WX 3 puts the window's first 4 columns off the left edge, so screen
+        // X 0 shows the middle of its first tile
+        assert_eq!(window_tile_starts(3), [4, 12, 20]);
+    }
+
+    // Colors at screen X 0 (BG), 40 (a sprite) and 100 (the window) of a
+    // line where the BG and window are solid color 3 and the sprite color 1
+    fn layer_colors(ppu: &mut PPU) -> [Color; 3] {
+        let mut mem = Mmu::init();
+        for row in 0..8 {
+            mem.set(0x8000 + row * 2, 0xFF);
+            mem.set(0x8001 + row * 2, 0xFF);
+            mem.set(0x8010 + row * 2, 0xFF);
+        }
+        place_sprite(&mut mem, 0, 16, 48);
+        mem.set(0xFE02, 1);
+        mem.set(LCDC as u16, 0xB3);
+        mem.set(BGP as u16, 0xE4);
+        mem.set(OBP0 as u16, 0xE4);
+        mem.set(WX as u16, 87);
+        mem.set(WY as u16, 0);
+        mem.set(LY as u16, 0);
+        ppu.scan_sprites(&mem);
+        ppu.draw_line(&mem).unwrap();
+        [0, 40, 100].map(|x| {
+            let pixel = &ppu.framebuffer()[x * 3..x * 3 + 3];
+            (pixel[0], pixel[1], pixel[2])
+        })
+    }
+
+    #[test]
+    fn test_layer_overrides_blank_their_layer() {
+        let [white, light, _, black] = GREEN_PALETTE;
+        let mut ppu = PPU::new();
+        assert_eq!(layer_colors(&mut ppu), [black, light, black]);
+
+        ppu.toggle_layer(Layer::Background);
+        assert!(ppu.force_bg_off);
+        assert_eq!(layer_colors(&mut ppu), [white, light, black]);
+        ppu.toggle_layer(Layer::Background);
+
+        ppu.toggle_layer(Layer::Window);
+        assert_eq!(layer_colors(&mut ppu), [black, light, white]);
+        ppu.toggle_layer(Layer::Window);
+
+        ppu.toggle_layer(Layer::Objects);
+        assert_eq!(layer_colors(&mut ppu), [black, black, black]);
+        ppu.toggle_layer(Layer::Objects);
+        assert!(!ppu.force_obj_off);
+        assert_eq!(layer_colors(&mut ppu), [black, light, black]);
+    }
+
+    // Color of the leftmost pixel of a tall sprite at the top of the screen,
+    // where tile 3's last row is the only one that isn't blank
+    fn tall_sprite_pixel(flags: u8, row: u8) -> u8 {
+        let mut mem = Mmu::init();
+        mem.set(0x8000 + 3 * 16 + 7 * 2, 0xFF);
+        mem.set(LY as u16, row);
+        let mut ppu = PPU::new();
+        ppu.tall_sprites = true;
+        ppu.lx = 8;
+        let sprite = OAM {
+            y: 16,
+            x: 8,
+            tile: 2,
+            flags,
+        };
+        ppu.push_sprite_tile_row(&mem, &sprite);
+        ppu.sprite_fifo[0].color
+    }
+
+    fn place_sprite(mem: &mut Mmu, index: u16, y: u8, x: u8) {
+        mem.set(0xFE00 + index * 4, y);
+        mem.set(0xFE01 + index * 4, x);
+    }
+
+    fn scanned_sprites(mem: &mut Mmu, line: u8) -> Vec<OAM> {
+        mem.set(LY as u16, line);
+        let mut ppu = PPU::new();
+        ppu.scan_sprites(mem);
+        ppu.sprite_buffer
+    }
+
+    #[test]
+    fn test_frame_skip_keeps_timing() {
+        let run = |frame_skip| {
+            let mut mem = Mmu::init();
+            for i in 0..10 {
+                mem.set(0xFE00 + i * 4, 16 + i as u8 * 4);
+                mem.set(0xFE01 + i * 4, 8 + i as u8 * 12);
+            }
+            mem.set(LCDC as u16, 0xB3);
+            mem.set(WY as u16, 40);
+            mem.set(WX as u16, 80);
+            mem.set(SCX as u16, 5);
+            mem.set(STAT as u16, 0x78);
+            mem.set(LY as u16, 0);
+            let mut ppu = PPU::new();
+            ppu.set_frame_skip(frame_skip);
+            let mut renderer = CountingRenderer { frames: 0 };
+            let mut states = Vec::new();
+            let mut finished = 0;
+            while finished < 7 {
+                let new_frame = ppu.render(&mut mem, 4, &mut renderer).unwrap();
+                finished += new_frame as usize;
+                states.push((new_frame, mem.get(LY), mem.get(STAT), mem.get(0xFF0F)));
+            }
+            (states, renderer.frames)
+        };
+        let (states, frames) = run(0);
+        let (skipped_states, skipped_frames) = run(2);
+        assert!(states == skipped_states);
+        // Frames 1, 4 and 7 are drawn
+        assert_eq!(frames, 7);
+        assert_eq!(skipped_frames, 3);
+    }
+
+    #[test]
+    fn test_window_counter_only_advances_on_drawn_lines() {
+        let mut mem = Mmu::init();
+        mem.set(LCDC as u16, 0xB1);
+        mem.set(WY as u16, 0);
+        mem.set(WX as u16, 7);
+        mem.set(LY as u16, 0);
+        let mut ppu = PPU::new();
+        let mut renderer = CountingRenderer { frames: 0 };
+        let mut run_to_line = |mem: &mut Mmu, ppu: &mut PPU, line: u8| {
+            while mem.get(LY) != line {
+                ppu.render(mem, 4, &mut renderer).unwrap();
+            }
+        };
+
+        run_to_line(&mut mem, &mut ppu, 3);
+        assert_eq!(ppu.window_counter, 3);
+        // Window disabled for a few lines, then moved off the right edge
+        mem.set(LCDC as u16, 0x91);
+        run_to_line(&mut mem, &mut ppu, 6);
+        mem.set(LCDC as u16, 0xB1);
+        mem.set(WX as u16, 200);
+        run_to_line(&mut mem, &mut ppu, 9);
+        assert_eq!(ppu.window_counter, 3);
+        // It picks up where it left off
+        mem.set(WX as u16, 7);
+        run_to_line(&mut mem, &mut ppu, 11);
+        assert_eq!(ppu.window_counter, 5);
+    }
+
+    #[test]
+    fn test_eleventh_sprite_on_line_is_dropped() {
+        let mut mem = Mmu::init();
+        // The 11th has the lowest X, but only OAM order counts
+        for i in 0..11 {
+            place_sprite(&mut mem, i, 16, 100 - i as u8 * 8);
+        }
+        let sprites = scanned_sprites(&mut mem, 0);
+        assert_eq!(sprites.len(), 10);
+        assert!(sprites.iter().all(|sprite| sprite.x != 20));
+    }
+
+    #[test]
+    fn test_sprites_at_x_0_use_up_the_line() {
+        let mut mem = Mmu::init();
+        // Sprites use tile 1, solid color 3, over a blank background
+        for row in 0..16 {
+            mem.set(0x8010 + row, 0xFF);
+        }
+        mem.set(LCDC as u16, 0x93);
+        mem.set(BGP as u16, 0xE4);
+        mem.set(OBP0 as u16, 0xE4);
+        for i in 0..11 {
+            place_sprite(&mut mem, i, 16, 0);
+            mem.set(0xFE02 + i * 4, 1);
+        }
+        place_sprite(&mut mem, 10, 16, 50);
+        let sprite_pixel = |mem: &mut Mmu| {
+            mem.set(LY as u16, 0);
+            let mut ppu = PPU::new();
+            ppu.scan_sprites(mem);
+            ppu.draw_line(mem).unwrap();
+            let offset = 42 * 3;
+            let pixel = &ppu.framebuffer()[offset..offset + 3];
+            (pixel[0], pixel[1], pixel[2])
+        };
+
+        let sprites = scanned_sprites(&mut mem, 0);
+        assert_eq!(sprites.len(), 10);
+        assert!(sprites.iter().all(|sprite| sprite.x == 0));
+        assert_eq!(sprite_pixel(&mut mem), GREEN_PALETTE[0]);
+
+        // With one fewer in the way, the 11th shows up
+        place_sprite(&mut mem, 0, 0, 0);
+        assert_eq!(sprite_pixel(&mut mem), GREEN_PALETTE[3]);
+    }
+
+    #[test]
+    fn test_sprite_y_range() {
+        let mut mem = Mmu::init();
+        place_sprite(&mut mem, 0, 16, 8);
+        assert_eq!(scanned_sprites(&mut mem, 0).len(), 1);
+        assert_eq!(scanned_sprites(&mut mem, 7).len(), 1);
+        assert!(scanned_sprites(&mut mem, 8).is_empty());
+
+        // Partly above the screen: only its bottom 3 rows are on lines 0-2
+        place_sprite(&mut mem, 0, 11, 8);
+        assert_eq!(scanned_sprites(&mut mem, 2).len(), 1);
+        assert!(scanned_sprites(&mut mem, 3).is_empty());
+
+        // Fully above the screen, and near the top of the Y range
+        place_sprite(&mut mem, 0, 8, 8);
+        assert!(scanned_sprites(&mut mem, 0).is_empty());
+        place_sprite(&mut mem, 0, 250, 8);
+        assert!(scanned_sprites(&mut mem, 143).is_empty());
+    }
+
+    #[test]
+    fn test_tall_sprite_vflip() {
+        assert_eq!(tall_sprite_pixel(0x00, 15), 1);
+        assert_eq!(tall_sprite_pixel(0x00, 0), 0);
+        assert_eq!(tall_sprite_pixel(0x40, 0), 1);
+        assert_eq!(tall_sprite_pixel(0x40, 8), 0);
+        assert_eq!(tall_sprite_pixel(0x40, 15), 0);
+    }
+
+    #[test]
+    fn test_tall_sprite_halves_split_at_row_8() {
+        let mut mem = Mmu::init();
+        mem.set(LCDC as u16, 0x00);
+        // Tile 2's last row is color 1 and tile 3's first row color 2
+        mem.set(0x8000 + 2 * 16 + 7 * 2, 0xFF);
+        mem.set(0x8000 + 3 * 16 + 1, 0xFF);
+        // An odd tile number, whose low bit tall sprites ignore
+        place_sprite(&mut mem, 0, 16, 8);
+        mem.set(0xFE02, 3);
+        let mut ppu = PPU::new();
+        ppu.tall_sprites = true;
+        let mut color_at = |ppu: &mut PPU, row| {
+            mem.set(LY as u16, row);
+            ppu.scan_sprites(&mem);
+            assert_eq!(ppu.sprite_buffer[0].tile, 3);
+            let sprite = ppu.sprite_buffer[0];
+            ppu.sprite_fifo.clear();
+            ppu.lx = 8;
+            ppu.push_sprite_tile_row(&mem, &sprite);
+            ppu.sprite_fifo[0].color
+        };
+        assert_eq!(color_at(&mut ppu, 7), 1);
+        assert_eq!(color_at(&mut ppu, 8), 2);
+    }
+
+    #[test]
+    fn test_select_sprite_equal_x_prefers_oam_order() {
+        // Two solid sprites at screen X 0, the first on OBP0 and the second
+        // on OBP1, which give color 3 different shades
+        let mem = MmuBuilder::new()
+            .tile(1, solid_tile(3))
+            .tile(2, solid_tile(3))
+            .oam(0, 16, 8, 1, 0)
+            .oam(1, 16, 8, 2, 0b0001_0000)
+            .reg(LCDC, 0x83)
+            .reg(BGP, 0xE4)
+            .reg(OBP0, 0xC0)
+            .reg(OBP1, 0x40)
+            .reg(LY, 0)
+            .build();
+        let mut ppu = PPU::new();
+        ppu.scan_sprites(&mem);
+        ppu.draw_line(&mem).unwrap();
+        let pixel = &ppu.framebuffer()[..3];
+        assert_eq!((pixel[0], pixel[1], pixel[2]), GREEN_PALETTE[3]);
+    }
+
+    #[test]
+    fn test_select_sprite_lower_x_wins() {
+        let sprites = [
+            OAM {
+                y: 16,
+                x: 10,
+                tile: 0,
+                flags: 0,
+            },
+            OAM {
+                y: 16,
+                x: 8,
+                tile: 1,
+                flags: 0b0001_0000,
+            },
+        ];
+        assert_eq!(select_sprite(&sprites, 10), Some(1));
+        assert_eq!(select_sprite(&sprites, 16), Some(0));
+        assert_eq!(select_sprite(&sprites, 20), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+
+use crate::core::input::Button;
+
+// First line of every replay file. Bump the version if the format changes.
+const HEADER: &str = "gumball-replay";
+const VERSION: u32 = 1;
+
+// A button change applied at the start of `frame`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ReplayInput {
+    pub frame: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+// Scripted input, counted in frames from power-on. The text format starts
+// with a `gumball-replay 1` line, followed by lines like `120 start down` or
+// `125 start up`. Blank lines and lines starting with `#` are skipped, and
+// frames must not go backwards.
+#[derive(Debug, Default)]
+pub struct Replay {
+    inputs: Vec<ReplayInput>,
+    next: usize,
+}
+
+fn parse_header(line: &str) -> Result<(), String> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [HEADER, version] if version == VERSION.to_string() => Ok(()),
+        [HEADER, version] => Err(format!("Unsupported replay version {version}")),
+        _ => Err(format!("Not a replay file, expected `{HEADER} {VERSION}`")),
+    }
+}
+
+fn parse_line(line: &str) -> Result<ReplayInput, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [frame, button, state] = fields[..] else {
+        return Err(format!(
+            "Expected `<frame> <button> <up|down>`, got `{line}`"
+        ));
+    };
+    let frame = frame
+        .parse()
+        .map_err(|_| format!("Invalid frame number `{frame}`"))?;
+    let button = Button::from_name(button).ok_or(format!("Unknown button `{button}`"))?;
+    let pressed = match state {
+        "down" => true,
+        "up" => false,
+        _ => return Err(format!("Expected `up` or `down`, got `{state}`")),
+    };
+    Ok(ReplayInput {
+        frame,
+        button,
+        pressed,
+    })
+}
+
+impl Replay {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let (_, header) = lines.next().ok_or("Replay file is empty")?;
+        parse_header(header)?;
+
+        let mut inputs: Vec<ReplayInput> = Vec::new();
+        for (number, line) in lines {
+            let input = parse_line(line).map_err(|e| format!("Line {}: {e}", number + 1))?;
+            if inputs.last().is_some_and(|last| last.frame > input.frame) {
+                return Err(format!(
+                    "Line {}: frame {} comes after a later frame",
+                    number + 1,
+                    input.frame
+                ));
+            }
+            inputs.push(input);
+        }
+        Ok(Self { inputs, next: 0 })
+    }
+
+    // Append an input while recording. Frames must not go backwards.
+    pub fn push(&mut self, input: ReplayInput) {
+        debug_assert!(self
+            .inputs
+            .last()
+            .is_none_or(|last| last.frame <= input.frame));
+        self.inputs.push(input);
+    }
+
+    // The inputs due by `frame` that haven't been taken yet
+    pub fn take_due(&mut self, frame: u64) -> &[ReplayInput] {
+        let start = self.next;
+        while self
+            .inputs
+            .get(self.next)
+            .is_some_and(|input| input.frame <= frame)
+        {
+            self.next += 1;
+        }
+        &self.inputs[start..self.next]
+    }
+
+    // Play again from the first input, after the machine is reset
+    pub fn rewind(&mut self) {
+        self.next = 0;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next == self.inputs.len()
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{HEADER} {VERSION}")?;
+        for input in &self.inputs {
+            let state = if input.pressed { "down" } else { "up" };
+            writeln!(f, "{} {} {}", input.frame, input.button.name(), state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_replay() {
+        let mut replay = Replay::parse(
+            "gumball-replay 1\n# title screen\n0 start down\n\n2 START up\n2 a down\n",
+        )
+        .unwrap();
+        assert_eq!(
+            replay.take_due(0),
+            [ReplayInput {
+                frame: 0,
+                button: Button::Start,
+                pressed: true,
+            }]
+        );
+        assert!(replay.take_due(1).is_empty());
+        assert_eq!(replay.take_due(2).len(), 2);
+        assert!(replay.finished());
+        replay.rewind();
+        assert_eq!(replay.take_due(0).len(), 1);
+    }
+
+    #[test]
+    fn test_parse_replay_errors() {
+        assert_eq!(
+            Replay::parse("0 start down").unwrap_err(),
+            "Not a replay file, expected `gumball-replay 1`"
+        );
+        assert_eq!(
+            Replay::parse("gumball-replay 2\n").unwrap_err(),
+            "Unsupported replay version 2"
+        );
+        assert_eq!(
+            Replay::parse("gumball-replay 1\n0 start down\nx a up").unwrap_err(),
+            "Line 3: Invalid frame number `x`"
+        );
+        assert_eq!(
+            Replay::parse("gumball-replay 1\n0 turbo down").unwrap_err(),
+            "Line 2: Unknown button `turbo`"
+        );
+        assert_eq!(
+            Replay::parse("gumball-replay 1\n5 a down\n4 a up").unwrap_err(),
+            "Line 3: frame 4 comes after a later frame"
+        );
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let mut replay = Replay::default();
+        replay.push(ReplayInput {
+            frame: 3,
+            button: Button::Left,
+            pressed: true,
+        });
+        replay.push(ReplayInput {
+            frame: 10,
+            button: Button::Left,
+            pressed: false,
+        });
+        let text = replay.to_string();
+        assert_eq!(text, "gumball-replay 1\n3 left down\n10 left up\n");
+        assert_eq!(Replay::parse(&text).unwrap().inputs, replay.inputs);
+    }
+}
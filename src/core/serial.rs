@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::core::interrupts::Interrupt;
+use crate::core::mmu::Mmu;
+
+const SB: usize = 0xFF01;
+const SC: usize = 0xFF02;
+// The internal clock shifts a bit every 512 cycles (8192 Hz)
+const CYCLES_PER_BIT: u32 = 512;
+
+// The other end of the link cable. `exchange` sends our byte and returns
+// theirs, as if all eight bits had been shifted across.
+pub trait SerialPeer {
+    fn exchange(&mut self, out: u8) -> u8;
+}
+
+// A peer that keeps every byte sent to it and sends back 0xFF, like an
+// unplugged cable. Test ROMs print their results this way. Clones share the
+// bytes, so one can go to the emulator and another read them back.
+#[derive(Clone, Debug, Default)]
+pub struct SerialCapture(Rc<RefCell<Vec<u8>>>);
+
+impl SerialCapture {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl SerialPeer for SerialCapture {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.0.borrow_mut().push(out);
+        0xFF
+    }
+}
+
+// A transfer starts when SC bit 7 is set. With the internal clock (SC bit 0)
+// we drive it and it takes 8 bit periods. With the external clock the peer
+// drives it, so with no peer connected it never completes.
+#[derive(Debug, Default)]
+pub struct Serial {
+    // Cycles since the current transfer started, if one is running
+    transfer_cycles: Option<u32>,
+}
+
+impl Serial {
+    pub fn update(&mut self, cycles: u32, mmu: &mut Mmu, peer: Option<&mut dyn SerialPeer>) {
+        let sc = mmu.get(SC);
+        if sc & 0x80 == 0 {
+            self.transfer_cycles = None;
+            return;
+        }
+        let internal_clock = sc & 0x01 != 0;
+        if !internal_clock && peer.is_none() {
+            return;
+        }
+        let elapsed = self.transfer_cycles.unwrap_or(0) + cycles;
+        if elapsed < 8 * CYCLES_PER_BIT {
+            self.transfer_cycles = Some(elapsed);
+            return;
+        }
+        // Nothing on the other end shifts in 1s
+        let received = peer.map_or(0xFF, |peer| peer.exchange(mmu.get(SB)));
+        mmu.set(SB as u16, received);
+        mmu.set(SC as u16, sc & 0x7F);
+        Interrupt::Serial.trigger(mmu);
+        self.transfer_cycles = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo(u8);
+
+    impl SerialPeer for Echo {
+        fn exchange(&mut self, out: u8) -> u8 {
+            std::mem::replace(&mut self.0, out)
+        }
+    }
+
+    fn start_transfer(mmu: &mut Mmu, sc: u8) {
+        mmu.set(SB as u16, 0x42);
+        mmu.set(SC as u16, sc);
+        mmu.set(0xFF0F, 0);
+    }
+
+    #[test]
+    fn test_internal_clock_completes_after_8_bits() {
+        let mut mmu = Mmu::init();
+        let mut serial = Serial::default();
+        start_transfer(&mut mmu, 0x81);
+        serial.update(8 * CYCLES_PER_BIT - 4, &mut mmu, None);
+        assert_eq!(mmu.get(SC) & 0x80, 0x80);
+        serial.update(4, &mut mmu, None);
+        assert_eq!(mmu.get(SC) & 0x80, 0);
+        assert_eq!(mmu.get(SB), 0xFF);
+        assert_eq!(mmu.get(0xFF0F) & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_peer() {
+        let mut mmu = Mmu::init();
+        let mut serial = Serial::default();
+        start_transfer(&mut mmu, 0x80);
+        serial.update(100 * CYCLES_PER_BIT, &mut mmu, None);
+        assert_eq!(mmu.get(SC) & 0x80, 0x80);
+        assert_eq!(mmu.get(SB), 0x42);
+
+        let mut peer = Echo(0x17);
+        serial.update(8 * CYCLES_PER_BIT, &mut mmu, Some(&mut peer));
+        assert_eq!(mmu.get(SB), 0x17);
+        assert_eq!(peer.0, 0x42);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use crate::core::mmu::Mmu;
+
+// Labels from an RGBDS or no$gmb .sym file. Each line is `BB:AAAA Label`
+// with the bank and address in hex, and `;` starts a comment.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    // Keyed by `key(bank, address)`
+    labels: HashMap<usize, String>,
+    addresses: HashMap<String, usize>,
+}
+
+fn key(bank: usize, address: u16) -> usize {
+    (bank << 16) | address as usize
+}
+
+// Addresses in the same bank only count as near each other within one of
+// these areas, so a label in WRAM doesn't name an HRAM address
+fn region(address: u16) -> u16 {
+    match address {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        _ => 0xE000,
+    }
+}
+
+// The bank a .sym file would list `address` under, given what's mapped
+pub fn mapped_bank(mem: &Mmu, address: u16) -> usize {
+    match address {
+        0x4000..=0x7FFF => mem.current_rom_bank(),
+        0xA000..=0xBFFF => mem.current_ram_bank(),
+        // WRAMX is bank 1 on a DMG
+        0xD000..=0xDFFF => 1,
+        _ => 0,
+    }
+}
+
+// Where the debugger stops: an address, and for a symbol the bank it's in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Location {
+    pub bank: Option<usize>,
+    pub address: u16,
+}
+
+impl Location {
+    pub fn new(address: u16) -> Self {
+        Location {
+            bank: None,
+            address,
+        }
+    }
+
+    // Whether PC at `pc` is here. A banked location only counts while its
+    // bank is mapped.
+    pub fn contains(&self, mem: &Mmu, pc: u16) -> bool {
+        pc == self.address && self.bank.is_none_or(|bank| mapped_bank(mem, pc) == bank)
+    }
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Symbols::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid symbol on line {}: {line}", number + 1);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, address) = location.split_once(':').ok_or_else(invalid)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            let name = name.trim().to_string();
+            symbols.labels.insert(key(bank, address), name.clone());
+            symbols.addresses.insert(name, key(bank, address));
+        }
+        Ok(symbols)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // The label at exactly `address` in `bank`
+    pub fn label(&self, bank: usize, address: u16) -> Option<&str> {
+        self.labels.get(&key(bank, address)).map(String::as_str)
+    }
+
+    // The bank and address of the label `name`
+    pub fn address_of(&self, name: &str) -> Option<(usize, u16)> {
+        self.addresses.get(name).map(|&key| (key >> 16, key as u16))
+    }
+
+    pub fn location_of(&self, name: &str) -> Option<Location> {
+        self.address_of(name).map(|(bank, address)| Location {
+            bank: Some(bank),
+            address,
+        })
+    }
+
+    // The closest label at or before `address`, and how far past it
+    // `address` is
+    pub fn nearest(&self, bank: usize, address: u16) -> Option<(&str, u16)> {
+        let start = key(bank, region(address));
+        let end = key(bank, address);
+        self.labels
+            .iter()
+            .filter(|(&key, _)| (start..=end).contains(&key))
+            .max_by_key(|(&key, _)| key)
+            .map(|(&key, name)| (name.as_str(), (end - key) as u16))
+    }
+
+    // `Label` or `Label+0x12`, for debugger output
+    pub fn describe(&self, bank: usize, address: u16) -> Option<String> {
+        self.nearest(bank, address)
+            .map(|(name, offset)| match offset {
+                0 => name.to_string(),
+                offset => format!("{name}+{offset:#x}"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM_FILE: &str = "; File generated by rgblink
+00:0150 Main
+00:0158 Main.loop
+01:4000 BankedRoutine
+00:c000 wBuffer
+00:ff80 hDMARoutine
+";
+
+    #[test]
+    fn test_parse_sym_file() {
+        let symbols = Symbols::parse(SYM_FILE).unwrap();
+        assert_eq!(symbols.label(0, 0x0150), Some("Main"));
+        assert_eq!(symbols.label(1, 0x4000), Some("BankedRoutine"));
+        assert_eq!(symbols.label(2, 0x4000), None);
+        assert_eq!(symbols.address_of("Main.loop"), Some((0, 0x0158)));
+        assert_eq!(symbols.address_of("Missing"), None);
+
+        assert!(Symbols::parse("0150 Main").is_err());
+        assert!(Symbols::parse("00:zz50 Main").is_err());
+        assert!(Symbols::parse("; only a comment\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_nearest_symbol_in_bank_and_region() {
+        let symbols = Symbols::parse(SYM_FILE).unwrap();
+        assert_eq!(symbols.describe(0, 0x0150).as_deref(), Some("Main"));
+        assert_eq!(symbols.describe(0, 0x0155).as_deref(), Some("Main+0x5"));
+        assert_eq!(
+            symbols.describe(0, 0x015A).as_deref(),
+            Some("Main.loop+0x2")
+        );
+        assert_eq!(symbols.describe(0, 0x0100), None);
+        assert_eq!(
+            symbols.describe(1, 0x4010).as_deref(),
+            Some("BankedRoutine+0x10")
+        );
+        assert_eq!(symbols.describe(2, 0x4010), None);
+        // wBuffer is in WRAM, so it doesn't name anything in HRAM
+        assert_eq!(
+            symbols.describe(0, 0xFF90).as_deref(),
+            Some("hDMARoutine+0x10")
+        );
+        assert_eq!(symbols.describe(0, 0xFF00), None);
+    }
+
+    #[test]
+    fn test_mapped_bank_follows_rom_bank() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x147] = 0x01;
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        assert_eq!(mapped_bank(&mmu, 0x0150), 0);
+        assert_eq!(mapped_bank(&mmu, 0x4010), 1);
+        mmu.set(0x2000, 0x03);
+        assert_eq!(mapped_bank(&mmu, 0x4010), 3);
+        assert_eq!(mapped_bank(&mmu, 0xC000), 0);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::core::mmu::Mmu;
+
+// Sets up an `Mmu` for PPU tests. VRAM and OAM are written directly, so the
+// PPU mode doesn't block them, while registers go through `Mmu::set` and get
+// its side effects.
+pub struct MmuBuilder {
+    mmu: Mmu,
+}
+
+impl Default for MmuBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmuBuilder {
+    pub fn new() -> Self {
+        MmuBuilder { mmu: Mmu::init() }
+    }
+
+    // Tile `index` in the 0x8000 block, 16 bytes of two bitplanes per row
+    pub fn tile(mut self, index: usize, data: [u8; 16]) -> Self {
+        let start = 0x8000 + index * 16;
+        for (offset, byte) in data.into_iter().enumerate() {
+            self.mmu[start + offset] = byte;
+        }
+        self
+    }
+
+    // OAM entry `slot` (0-39)
+    pub fn oam(mut self, slot: usize, y: u8, x: u8, tile: u8, flags: u8) -> Self {
+        let start = 0xFE00 + slot * 4;
+        for (offset, byte) in [y, x, tile, flags].into_iter().enumerate() {
+            self.mmu[start + offset] = byte;
+        }
+        self
+    }
+
+    // The tile at column `x`, row `y` of tile map 0 (0x9800) or 1 (0x9C00)
+    pub fn tilemap(mut self, map: usize, x: usize, y: usize, tile: u8) -> Self {
+        self.mmu[0x9800 + map * 0x400 + y * 32 + x] = tile;
+        self
+    }
+
+    pub fn reg(mut self, address: usize, value: u8) -> Self {
+        self.mmu.set(address as u16, value);
+        self
+    }
+
+    pub fn build(self) -> Mmu {
+        self.mmu
+    }
+}
+
+// A tile whose rows are all color `color`
+pub fn solid_tile(color: u8) -> [u8; 16] {
+    let low = if color & 1 != 0 { 0xFF } else { 0x00 };
+    let high = if color & 2 != 0 { 0xFF } else { 0x00 };
+    [low, high].repeat(8).try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_writes_vram_oam_and_registers() {
+        let mmu = MmuBuilder::new()
+            .reg(0xFF40, 0x91)
+            .tile(1, solid_tile(2))
+            .tilemap(1, 3, 2, 1)
+            .oam(39, 16, 8, 1, 0x20)
+            .reg(0xFF47, 0xE4)
+            .build();
+        assert_eq!(mmu.peek(0x8010), 0x00);
+        assert_eq!(mmu.peek(0x8011), 0xFF);
+        assert_eq!(mmu.peek(0x9C00 + 2 * 32 + 3), 1);
+        assert_eq!(mmu.peek(0xFE9C), 16);
+        assert_eq!(mmu.peek(0xFE9F), 0x20);
+        assert_eq!(mmu.get(0xFF47), 0xE4);
+    }
+}
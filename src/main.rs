@@ -1,39 +1,65 @@
-mod apu;
+#[cfg(feature = "frontend")]
 mod background;
-mod cpu;
 mod debugger;
-mod emulator;
-mod input;
-mod interrupts;
-mod mmu;
-mod ppu;
-mod registers;
+#[cfg(feature = "frontend")]
+mod media;
+#[cfg(feature = "frontend")]
+mod scope;
+#[cfg(feature = "frontend")]
+mod tile_data;
+mod verify;
+#[cfg(feature = "frontend")]
 mod window;
 
-use clap::Parser;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-pub struct WindowCreator {
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
-    texture_creator: sdl2::render::TextureCreator<sdl2::video::WindowContext>,
+use clap::{Parser, Subcommand};
+use gumball::core::emulator::Emulator;
+use gumball::core::frontend::AudioSink;
+use gumball::core::mmu::{self, InitRam, Mmu, RomError};
+use gumball::core::ppu;
+use gumball::core::replay::Replay;
+use gumball::core::symbols::Symbols;
+
+use background::BackgroundDisplay;
+use media::{AudioConfig, SdlAudioSink, SdlEventQueue, SdlRenderer, WindowCreator};
+use scope::ScopeDisplay;
+use tile_data::TileDataDisplay;
+use window::WindowDisplay;
+
+// With no subcommand the `run` options can be given directly, so
+// `gumball -r game.gb` still works
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: Option<RunArgs>,
 }
 
-impl WindowCreator {
-    pub fn new(window: sdl2::video::Window) -> Self {
-        let canvas = window
-            .into_canvas()
-            .build()
-            .expect("Could not make a canvas");
-        let texture_creator = canvas.texture_creator();
-        Self {
-            canvas,
-            texture_creator,
-        }
-    }
+// Parsed once, so RunArgs being much bigger than the rest doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Play a ROM (the default)
+    Run(RunArgs),
+    /// Run a test ROM headless and check its serial output for "Passed"
+    Verify(VerifyArgs),
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    rom_path: String,
+    /// Give up after this many emulated frames (about 60 a second)
+    #[arg(long, default_value_t = 3600)]
+    frames: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
     #[arg(short, long)]
     rom_path: String,
     #[arg(short, long)]
@@ -42,23 +68,203 @@ struct Args {
     debug: bool,
     #[arg(short, long)]
     window: bool,
+    #[arg(short, long)]
+    tiles: bool,
+    /// Show each sound channel's waveform in a debug window
+    #[arg(long)]
+    scope: bool,
+    #[arg(long)]
+    profile: bool,
+    #[arg(long)]
+    boot_rom: Option<String>,
+    /// Power-on WRAM/HRAM contents: zero, pattern, random or random:<seed>
+    #[arg(long, default_value = "zero", value_parser = InitRam::parse)]
+    init_ram: InitRam,
+    /// green, gray, pocket, or four comma-separated hex colors
+    #[arg(long, value_parser = ppu::parse_palette)]
+    palette: Option<ppu::Colors>,
+    /// Window size as a multiple of the 160x144 screen
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=16))]
+    scale: u32,
+    #[arg(long)]
+    fullscreen: bool,
+    /// Draw one frame, then skip this many, to save time on slow machines
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=9))]
+    frame_skip: u32,
+    /// Run this many times faster than the Game Boy, from 0.25 to 4
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+    /// Sync to the display's refresh rate instead of the Game Boy's ~59.7 Hz
+    #[arg(long)]
+    vsync: bool,
+    /// Most audio to queue ahead, in milliseconds
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(10..=1000))]
+    audio_latency_ms: u32,
+    /// Play sound in mono instead of stereo
+    #[arg(long)]
+    mono: bool,
+    /// Run without sound, e.g. on a machine with no audio device
+    #[arg(long)]
+    no_audio: bool,
+    /// Game Genie (ABC-DEF-GHI) or GameShark (ABCDEFGH) code, may be repeated
+    #[arg(long)]
+    cheat: Vec<String>,
+    /// Write every button press and release to this file on exit
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<String>,
+    /// Play back a file written with --record, ignoring the keyboard
+    #[arg(long)]
+    replay: Option<String>,
+    /// Write the registers and instruction to this file before every instruction
+    #[arg(long)]
+    trace: Option<String>,
+    /// Start the trace the first time PC reaches this hex address, e.g. 0x0100
+    #[arg(long, requires = "trace", value_parser = parse_address)]
+    trace_start: Option<u16>,
+    /// RGBDS or no$gmb .sym file naming addresses in the debugger
+    #[arg(long)]
+    symbols: Option<String>,
+    /// Seconds between saves of battery-backed RAM to the .sav file, or 0 to
+    /// save only on exit
+    #[arg(long, default_value_t = 30)]
+    autosave_interval: u64,
+    /// Reload the ROM whenever the file changes, for homebrew development
+    #[arg(long)]
+    watch: bool,
+    /// Raise a spurious STAT interrupt on writes to STAT, like a DMG does
+    #[arg(long)]
+    stat_write_bug: bool,
+    /// Ignore Left+Right and Up+Down pressed together, which a real d-pad can't do
+    #[arg(long)]
+    block_opposite_directions: bool,
+}
+
+fn parse_speed(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(speed) if (0.25..=4.0).contains(&speed) => Ok(speed),
+        _ => Err(format!("Invalid speed {text}, expected 0.25 to 4")),
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let hex = text.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid address {text}"))
+}
+
+fn load_cartridge(path: &str, init_ram: InitRam) -> Result<Mmu, RomError> {
+    let rom = mmu::load_rom(path)?;
+    if let Err(e) = mmu::check_header_checksum(&rom) {
+        eprintln!("Warning: {e}");
+    }
+    let mut mem = Mmu::init_with_ram(init_ram);
+    mem.initialize_from_bytes(&rom)?;
+    Ok(mem)
+}
+
+// game.gb saves to game.sav
+fn save_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("sav")
+}
+
+// Restore the game's .sav file, if it has one
+fn load_save(mem: &mut Mmu, path: &Path) -> Result<(), String> {
+    match std::fs::read(path) {
+        Ok(data) => mem.load_save_data(&data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn load_boot_rom(mem: &mut Mmu, path: &str) -> Result<(), RomError> {
+    let boot_rom = std::fs::read(path)?;
+    mem.load_boot_rom(&boot_rom)
+}
+
+// A missing or broken audio device shouldn't stop the game, so carry on
+// without sound
+fn open_audio(sdl_context: &sdl2::Sdl, config: AudioConfig) -> Option<Box<dyn AudioSink>> {
+    let audio = sdl_context
+        .audio()
+        .and_then(|audio_subsystem| SdlAudioSink::new(&audio_subsystem, config));
+    match audio {
+        Ok(audio) => Some(Box::new(audio)),
+        Err(e) => {
+            eprintln!("Warning: could not open audio device, running without sound: {e}");
+            None
+        }
+    }
+}
+
+fn load_symbols(path: &str) -> Result<Symbols, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Symbols::parse(&text)
+}
+
+fn load_replay(path: &str) -> Result<Replay, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Replay::parse(&text)
+}
+
+// Exit 0 if the ROM printed "Passed", or 1 and whatever it did print if not
+fn verify(args: &VerifyArgs) -> i32 {
+    let mem = load_cartridge(&args.rom_path, InitRam::Zero).unwrap_or_else(|e| {
+        eprintln!("Error loading rom: {e}");
+        std::process::exit(1);
+    });
+    match verify::verify_rom(mem, args.frames) {
+        Ok(verdict) if verdict.passed => 0,
+        Ok(verdict) => {
+            eprintln!("Test did not pass. Serial output:\n{}", verdict.output);
+            1
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
 }
 
 fn main() {
-    let args = Args::parse();
-    let rom = mmu::load_rom(&args.rom_path);
-    let mut mem = mmu::Mmu::init();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Verify(args)) => std::process::exit(verify(&args)),
+        Some(Command::Run(args)) => run(args),
+        // Without a subcommand clap insists on the `run` options
+        None => run(cli.run.unwrap()),
+    }
+}
+
+fn run(args: RunArgs) {
+    let mut mem = load_cartridge(&args.rom_path, args.init_ram).unwrap_or_else(|e| {
+        eprintln!("Error loading rom: {e}");
+        std::process::exit(1);
+    });
+    let save_path = save_path(&args.rom_path);
+    if let Err(e) = load_save(&mut mem, &save_path) {
+        eprintln!("Error loading {}: {e}", save_path.display());
+        std::process::exit(1);
+    }
+    mem.set_stat_write_bug(args.stat_write_bug);
+    mem.input
+        .set_block_opposite_directions(args.block_opposite_directions);
+    if let Some(path) = &args.boot_rom {
+        if let Err(e) = load_boot_rom(&mut mem, path) {
+            eprintln!("Error loading boot rom: {e}");
+            std::process::exit(1);
+        }
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context
         .video()
         .expect("Could not initialize video subsystem");
-    let audio_subsystem = sdl_context
-        .audio()
-        .expect("Could not initialize audio subsystem");
-    let main_window = video_subsystem
-        .window("Gumball", 160, 144)
-        .position_centered()
+    let mut main_window_builder =
+        video_subsystem.window("Gumball", 160 * args.scale, 144 * args.scale);
+    main_window_builder.position_centered().resizable();
+    if args.fullscreen {
+        main_window_builder.fullscreen_desktop();
+    }
+    let main_window = main_window_builder
         .build()
         .expect("Could not initialize video subsystem");
 
@@ -75,7 +281,11 @@ fn main() {
 
     // let bg_texture_creator = background_window.map(|c| c.texture_creator());
 
-    let mut main_window_creator = WindowCreator::new(main_window);
+    let mut main_window_creator = if args.vsync {
+        WindowCreator::with_vsync(main_window)
+    } else {
+        WindowCreator::new(main_window)
+    };
 
     let bg_window_creator = args.background.then(|| {
         WindowCreator::new(
@@ -87,6 +297,16 @@ fn main() {
         )
     });
 
+    let tile_data_window_creator = args.tiles.then(|| {
+        WindowCreator::new(
+            video_subsystem
+                .window("Tile Data", 256, 384)
+                .position_centered()
+                .build()
+                .expect("Could not initialize video subsystem"),
+        )
+    });
+
     let window_window = args.window.then(|| {
         video_subsystem
             .window("Window", 256, 256)
@@ -98,25 +318,139 @@ fn main() {
             .expect("Could not make a canvas for the background display")
     });
 
+    let scope_window = args.scope.then(|| {
+        video_subsystem
+            .window("Scope", scope::WIDTH, scope::HEIGHT)
+            .position_centered()
+            .build()
+            .expect("Could not initialize video subsystem")
+            .into_canvas()
+            .build()
+            .expect("Could not make a canvas for the scope")
+    });
+
     let event_pump = sdl_context.event_pump().unwrap();
 
-    match rom {
-        Ok(rom) => {
-            mem.initialize_memory(rom);
-            let emulator = emulator::Emulator::new(
-                &mut main_window_creator.canvas,
-                main_window_creator
-                    .texture_creator
-                    .create_texture_streaming(sdl2::pixels::PixelFormatEnum::RGB24, 160, 144)
-                    .expect("Could not create texture"),
-                mem,
-                &audio_subsystem,
-                event_pump,
-                bg_window_creator,
-                window_window,
-            );
-            let _ = emulator.and_then(|mut e| Ok(e.run(args.debug).map_err(|e| println!("{}", e))));
-        }
-        Err(e) => panic!("Error loading rom: {e}"),
+    let renderer = SdlRenderer::new(&mut main_window_creator).expect("Could not create texture");
+    let audio_config = AudioConfig {
+        force_mono: args.mono,
+        ..AudioConfig::with_latency(args.audio_latency_ms)
+    };
+    let audio = if args.no_audio {
+        None
+    } else {
+        open_audio(&sdl_context, audio_config)
+    };
+    let emulator = Emulator::new(
+        Box::new(renderer),
+        Box::new(SdlEventQueue::new(event_pump)),
+        mem,
+        audio,
+    );
+    let _ = emulator.map(|mut e| {
+        if let Some(background) = bg_window_creator {
+            e.add_debug_view(Box::new(BackgroundDisplay::new(background)));
+        }
+        if let Some(window) = window_window {
+            e.add_debug_view(Box::new(WindowDisplay::new(window)));
+        }
+        if let Some(tile_data) = tile_data_window_creator {
+            e.add_debug_view(Box::new(TileDataDisplay::new(tile_data)));
+        }
+        if let Some(scope) = scope_window {
+            e.add_audio_view(Box::new(ScopeDisplay::new(scope)));
+        }
+        if let Some(palette) = args.palette {
+            e.set_palette(palette);
+        }
+        e.set_speed(args.speed);
+        e.set_frame_skip(args.frame_skip);
+        e.set_save_file(&save_path, Duration::from_secs(args.autosave_interval));
+        if args.watch {
+            e.watch_rom(&args.rom_path);
+        }
+        if args.profile {
+            e.enable_profiling();
+        }
+        for code in &args.cheat {
+            if let Err(err) = e.add_cheat(code) {
+                eprintln!("Invalid cheat {code}: {err}");
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = &args.replay {
+            match load_replay(path) {
+                Ok(replay) => e.set_replay(replay),
+                Err(err) => {
+                    eprintln!("Error loading replay: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        if args.record.is_some() {
+            e.start_recording();
+        }
+        if let Some(path) = &args.symbols {
+            match load_symbols(path) {
+                Ok(symbols) => e.set_symbols(symbols),
+                Err(err) => {
+                    eprintln!("Error loading symbols: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(path) = &args.trace {
+            match std::fs::File::create(path) {
+                Ok(file) => e.set_trace(Box::new(BufWriter::new(file)), args.trace_start),
+                Err(err) => {
+                    eprintln!("Error creating trace file: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        let result = e.run(args.debug).map_err(|e| println!("{}", e));
+        if let (Some(path), Some(recording)) = (&args.record, e.take_recording()) {
+            if let Err(err) = std::fs::write(path, recording.to_string()) {
+                eprintln!("Error writing recording: {err}");
+            }
+        }
+        if args.profile {
+            e.print_profile();
+        }
+        result
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_subcommands() {
+        let cli = Cli::try_parse_from(["gumball", "-r", "game.gb"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.run.unwrap().rom_path, "game.gb");
+        assert!(Cli::try_parse_from(["gumball"]).is_err());
+        assert!(Cli::try_parse_from(["gumball", "--scale", "4"]).is_err());
+
+        let cli = Cli::try_parse_from(["gumball", "run", "-r", "game.gb"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Run(args)) if args.rom_path == "game.gb"));
+
+        let cli = Cli::try_parse_from(["gumball", "verify", "test.gb", "--frames", "10"]).unwrap();
+        let Some(Command::Verify(args)) = cli.command else {
+            panic!("Expected verify");
+        };
+        assert_eq!((args.rom_path.as_str(), args.frames), ("test.gb", 10));
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("0.25"), Ok(0.25));
+        assert_eq!(parse_speed("4"), Ok(4.0));
+        assert!(parse_speed("NaN").is_err());
+        assert!(parse_speed("inf").is_err());
+        assert!(parse_speed("0.1").is_err());
+        assert!(parse_speed("5").is_err());
+        assert!(parse_speed("fast").is_err());
     }
 }
@@ -0,0 +1,310 @@
+use gumball::core::frontend::{AudioSink, Event, EventQueue, Renderer};
+use gumball::core::input::Button;
+use gumball::core::ppu::Layer;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioFormatNum, AudioSpecDesired};
+use sdl2::event::Event as SdlEvent;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum::RGB24;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::{AudioSubsystem, EventPump};
+use std::sync::{Arc, Mutex};
+
+pub struct WindowCreator {
+    pub canvas: Canvas<Window>,
+    pub texture_creator: TextureCreator<WindowContext>,
+    pub vsync: bool,
+}
+
+impl WindowCreator {
+    pub fn new(window: Window) -> Self {
+        Self::build(window, false)
+    }
+
+    // Presenting the canvas waits for the display's vertical blank
+    pub fn with_vsync(window: Window) -> Self {
+        Self::build(window, true)
+    }
+
+    fn build(window: Window, vsync: bool) -> Self {
+        let mut builder = window.into_canvas();
+        if vsync {
+            builder = builder.present_vsync();
+        }
+        let canvas = builder.build().expect("Could not make a canvas");
+        let texture_creator = canvas.texture_creator();
+        Self {
+            canvas,
+            texture_creator,
+            vsync,
+        }
+    }
+}
+
+pub struct SdlRenderer<'a> {
+    canvas: &'a mut Canvas<Window>,
+    texture: Texture<'a>,
+    vsync: bool,
+}
+
+impl<'a> SdlRenderer<'a> {
+    pub fn new(window_creator: &'a mut WindowCreator) -> Result<Self, String> {
+        // Scale the screen by whole pixels however big the window is, with
+        // letterboxing in fullscreen or when the window is resized to an
+        // odd shape. SDL rescales on resize by itself.
+        window_creator
+            .canvas
+            .set_logical_size(160, 144)
+            .map_err(|e| e.to_string())?;
+        window_creator.canvas.set_integer_scale(true)?;
+        window_creator
+            .canvas
+            .window_mut()
+            .set_minimum_size(160, 144)
+            .map_err(|e| e.to_string())?;
+        let texture = window_creator
+            .texture_creator
+            .create_texture_streaming(RGB24, 160, 144)
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            canvas: &mut window_creator.canvas,
+            texture,
+            vsync: window_creator.vsync,
+        })
+    }
+}
+
+impl Renderer for SdlRenderer<'_> {
+    fn render_frame(&mut self, pixels: &[u8]) -> Result<(), String> {
+        self.texture
+            .update(None, pixels, 160 * 3)
+            .map_err(|e| e.to_string())?;
+        // Blank the letterbox, which may have moved since the last frame
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn paces_frames(&self) -> bool {
+        self.vsync
+    }
+}
+
+fn key_to_button(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::Z => Some(Button::A),
+        Keycode::X => Some(Button::B),
+        Keycode::Return => Some(Button::Start),
+        Keycode::RShift => Some(Button::Select),
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Right => Some(Button::Right),
+        _ => None,
+    }
+}
+
+// Maps window and keyboard events to emulator events
+pub struct SdlEventQueue {
+    event_pump: EventPump,
+}
+
+impl SdlEventQueue {
+    pub fn new(event_pump: EventPump) -> Self {
+        Self { event_pump }
+    }
+
+    fn map_event(event: SdlEvent) -> Option<Event> {
+        match event {
+            SdlEvent::Quit { .. }
+            | SdlEvent::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => Some(Event::Quit),
+            SdlEvent::KeyDown {
+                keycode: Some(Keycode::P),
+                repeat: false,
+                ..
+            } => Some(Event::TogglePause),
+            SdlEvent::KeyDown {
+                keycode: Some(Keycode::N),
+                ..
+            } => Some(Event::Step),
+            SdlEvent::KeyDown {
+                keycode: Some(key @ (Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4)),
+                repeat: false,
+                ..
+            } => Some(Event::ToggleChannel(
+                (key as i32 - Keycode::Num1 as i32 + 1) as usize,
+            )),
+            SdlEvent::KeyDown {
+                keycode: Some(Keycode::Equals | Keycode::Plus | Keycode::KpPlus),
+                ..
+            } => Some(Event::SpeedUp),
+            SdlEvent::KeyDown {
+                keycode: Some(Keycode::Minus | Keycode::KpMinus),
+                ..
+            } => Some(Event::SpeedDown),
+            SdlEvent::KeyDown {
+                keycode: Some(key @ (Keycode::F1 | Keycode::F2 | Keycode::F3)),
+                repeat: false,
+                ..
+            } => Some(Event::ToggleLayer(match key {
+                Keycode::F1 => Layer::Background,
+                Keycode::F2 => Layer::Window,
+                _ => Layer::Objects,
+            })),
+            SdlEvent::KeyDown {
+                keycode: Some(Keycode::V),
+                ..
+            } => Some(Event::ToggleApng),
+            // Held keys repeat, but a held button is just still pressed
+            SdlEvent::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } => key_to_button(key).map(Event::ButtonDown),
+            SdlEvent::KeyUp {
+                keycode: Some(key), ..
+            } => key_to_button(key).map(Event::ButtonUp),
+            _ => None,
+        }
+    }
+}
+
+impl EventQueue for SdlEventQueue {
+    fn poll_event(&mut self) -> Option<Event> {
+        // Skip over events the emulator doesn't care about
+        loop {
+            if let Some(event) = Self::map_event(self.event_pump.poll_event()?) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+// Feeds the SDL audio callback from a queue the emulator fills
+struct SampleQueue {
+    buffer: Arc<Mutex<Vec<f32>>>,
+    position: usize,
+}
+
+impl AudioCallback for SampleQueue {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for x in out.iter_mut() {
+            if self.position < buffer.len() {
+                *x = buffer[self.position];
+                self.position += 1;
+            } else {
+                *x = Self::Channel::SILENCE;
+            }
+        }
+
+        if self.position > 0 {
+            buffer.drain(0..self.position);
+            self.position = 0;
+        }
+    }
+}
+
+const SAMPLE_RATE: i32 = 44100;
+
+// How far audio may lag behind the emulator. A longer latency rides out
+// slow frames without crackling but makes sound trail the picture.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioConfig {
+    // Samples past this much queued audio are dropped
+    pub latency_ms: u32,
+    // How many samples SDL asks for at a time
+    pub callback_samples: u16,
+    // Average the left and right mixes and open a one-channel device
+    pub force_mono: bool,
+}
+
+impl AudioConfig {
+    // The callback size follows the latency, so that several callbacks fit
+    // in the queue: the largest power of two up to an eighth of it
+    pub fn with_latency(latency_ms: u32) -> Self {
+        let eighth = (SAMPLE_RATE as u32 * latency_ms / 8000).clamp(64, 4096);
+        Self {
+            latency_ms,
+            callback_samples: 1 << eighth.ilog2(),
+            force_mono: false,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self::with_latency(100)
+    }
+}
+
+pub struct SdlAudioSink {
+    // Playback stops when the device is dropped
+    _device: AudioDevice<SampleQueue>,
+    buffer: Arc<Mutex<Vec<f32>>>,
+    sample_rate: i32,
+    channels: u8,
+    max_queued: usize,
+}
+
+impl SdlAudioSink {
+    pub fn new(audio_subsystem: &AudioSubsystem, config: AudioConfig) -> Result<Self, String> {
+        let desired_audio_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(if config.force_mono { 1 } else { 2 }),
+            samples: Some(config.callback_samples),
+        };
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let device = audio_subsystem.open_playback(None, &desired_audio_spec, |_| SampleQueue {
+            buffer: Arc::clone(&buffer),
+            position: 0,
+        })?;
+        let sample_rate = device.spec().freq;
+        let channels = device.spec().channels;
+        device.resume();
+        Ok(Self {
+            _device: device,
+            buffer,
+            sample_rate,
+            channels,
+            max_queued: sample_rate as usize * channels as usize * config.latency_ms as usize
+                / 1000,
+        })
+    }
+}
+
+impl AudioSink for SdlAudioSink {
+    fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    fn queue_samples(&mut self, samples: &[[f32; 2]]) {
+        // Drop samples rather than fall further behind than the latency
+        let mut buffer = self.buffer.lock().unwrap();
+        let room = self.max_queued.saturating_sub(buffer.len()) / self.channels as usize;
+        let samples = &samples[..samples.len().min(room)];
+        if self.channels == 1 {
+            buffer.extend(samples.iter().map(|[left, right]| (left + right) / 2.0));
+        } else {
+            buffer.extend(samples.iter().flatten());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_config_callback_size() {
+        assert_eq!(AudioConfig::default().callback_samples, 512);
+        assert_eq!(AudioConfig::with_latency(200).callback_samples, 1024);
+        assert_eq!(AudioConfig::with_latency(10).callback_samples, 64);
+    }
+}
@@ -0,0 +1,56 @@
+use gumball::core::apu::{ChannelHistory, HISTORY_LEN, MAX_AMPLITUDE};
+use gumball::core::frontend::AudioView;
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::{render::Canvas, video::Window};
+
+// Height of each channel's strip
+pub const LANE_HEIGHT: u32 = 64;
+pub const WIDTH: u32 = HISTORY_LEN as u32;
+pub const HEIGHT: u32 = LANE_HEIGHT * 4;
+
+const CHANNEL_COLORS: [Color; 4] = [
+    Color::RGB(0xff, 0x60, 0x60),
+    Color::RGB(0xff, 0xc0, 0x40),
+    Color::RGB(0x60, 0xc0, 0xff),
+    Color::RGB(0xa0, 0xff, 0x80),
+];
+
+// An oscilloscope of the four channels, one above the other, each showing
+// its most recent samples
+pub struct ScopeDisplay {
+    canvas: Canvas<Window>,
+}
+
+impl ScopeDisplay {
+    pub fn new(canvas: Canvas<Window>) -> Self {
+        Self { canvas }
+    }
+}
+
+impl AudioView for ScopeDisplay {
+    fn draw(&mut self, history: &ChannelHistory) -> Result<(), String> {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        let half_height = (LANE_HEIGHT / 2 - 2) as f32;
+        for (lane, color) in CHANNEL_COLORS.into_iter().enumerate() {
+            let middle = (lane as u32 * LANE_HEIGHT + LANE_HEIGHT / 2) as i32;
+            self.canvas.set_draw_color(Color::RGB(0x40, 0x40, 0x40));
+            self.canvas
+                .draw_line(Point::new(0, middle), Point::new(WIDTH as i32, middle))?;
+
+            let points: Vec<Point> = history
+                .channel(lane + 1)
+                .enumerate()
+                .map(|(x, sample)| {
+                    let y = middle - (sample / MAX_AMPLITUDE * half_height) as i32;
+                    Point::new(x as i32, y)
+                })
+                .collect();
+            self.canvas.set_draw_color(color);
+            self.canvas.draw_lines(&points[..])?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use gumball::core::frontend::DebugView;
+use gumball::core::mmu::Mmu;
+use gumball::core::ppu::{palette_color, Colors};
+use gumball::core::registers::BGP;
+
+use crate::background::color_index;
+use crate::media::WindowCreator;
+
+const TILE_DATA_START: usize = 0x8000;
+const TILE_COUNT: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const WIDTH: usize = TILES_PER_ROW * 8;
+const HEIGHT: usize = TILE_COUNT / TILES_PER_ROW * 8;
+
+pub struct TileDataDisplay {
+    window_creator: WindowCreator,
+}
+
+impl TileDataDisplay {
+    pub fn new(window_creator: WindowCreator) -> Self {
+        Self { window_creator }
+    }
+
+    pub fn draw_tiles(&mut self, mem: &Mmu, colors: &Colors) -> Result<(), String> {
+        let mut texture = self
+            .window_creator
+            .texture_creator
+            .create_texture_streaming(
+                sdl2::pixels::PixelFormatEnum::RGB24,
+                WIDTH as u32,
+                HEIGHT as u32,
+            )
+            .map_err(|e| e.to_string())?;
+
+        let pitch = WIDTH * 3;
+        let bgp = mem.get(BGP);
+
+        texture
+            .with_lock(None, |buffer: &mut [u8], _| {
+                for tile in 0..TILE_COUNT {
+                    let x = (tile % TILES_PER_ROW) * 8;
+                    let y = (tile / TILES_PER_ROW) * 8;
+                    let tile_addr = TILE_DATA_START + tile * 16;
+                    for j in 0..8 {
+                        let byte1 = mem.peek(tile_addr + j * 2);
+                        let byte2 = mem.peek(tile_addr + j * 2 + 1);
+                        for k in 0..8 {
+                            let (r, g, b) =
+                                palette_color(colors, bgp, color_index(byte1, byte2, k));
+                            let offset = (y + j) * pitch + (x + k) * 3;
+                            buffer[offset] = r;
+                            buffer[offset + 1] = g;
+                            buffer[offset + 2] = b;
+                        }
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        self.window_creator.canvas.copy(&texture, None, None)?;
+        self.window_creator.canvas.present();
+        Ok(())
+    }
+}
+
+impl DebugView for TileDataDisplay {
+    fn draw(&mut self, mem: &Mmu, colors: &Colors) -> Result<(), String> {
+        self.draw_tiles(mem, colors)
+    }
+}
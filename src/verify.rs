@@ -0,0 +1,82 @@
+use gumball::core::emulator::Emulator;
+use gumball::core::mmu::Mmu;
+use gumball::core::serial::SerialCapture;
+
+// Frames to run between checks of the serial output
+const CHECK_INTERVAL: usize = 60;
+
+pub struct Verdict {
+    pub passed: bool,
+    // Everything the ROM printed over the serial port
+    pub output: String,
+}
+
+// Run a Blargg-style test ROM headless for at most `max_frames` frames. These
+// print their results over the serial port, ending with "Passed" or "Failed".
+pub fn verify_rom(mmu: Mmu, max_frames: usize) -> Result<Verdict, String> {
+    let mut emulator = Emulator::headless(mmu)?;
+    let capture = SerialCapture::default();
+    emulator.set_serial_peer(Box::new(capture.clone()));
+
+    let mut frames = 0;
+    while frames < max_frames {
+        let chunk = CHECK_INTERVAL.min(max_frames - frames);
+        emulator.run_frames(chunk)?;
+        frames += chunk;
+        let output = capture.text();
+        if output.contains("Passed") || output.contains("Failed") {
+            break;
+        }
+    }
+    let output = capture.text();
+    Ok(Verdict {
+        passed: output.contains("Passed"),
+        output,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Prints the zero-terminated string at 0x0200 over the serial port, one
+    // byte at a time, then spins
+    fn printing_rom(text: &str) -> Mmu {
+        let mut rom = vec![0u8; 0x8000];
+        // JP 0x0150
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x167].copy_from_slice(&[
+            0x21, 0x00, 0x02, // LD HL, 0x0200
+            0x2A, // LD A, (HL+)
+            0xB7, // OR A
+            0x28, 0x0E, // JR Z, +14
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, 0x81, // LD A, 0x81
+            0xE0, 0x02, // LDH (SC), A
+            0xF0, 0x02, // LDH A, (SC)
+            0xE6, 0x80, // AND 0x80
+            0x20, 0xFA, // JR NZ, -6
+            0x18, 0xEE, // JR -18
+            0x18, 0xFE, // JR -2
+        ]);
+        rom[0x200..0x200 + text.len()].copy_from_slice(text.as_bytes());
+        let mut mmu = Mmu::init();
+        mmu.initialize_from_bytes(&rom).unwrap();
+        mmu
+    }
+
+    #[test]
+    fn test_verify_passes_on_passed_output() {
+        let verdict = verify_rom(printing_rom("cpu_instrs\n\nPassed\n"), 10).unwrap();
+        assert!(verdict.passed);
+        assert_eq!(verdict.output, "cpu_instrs\n\nPassed\n");
+    }
+
+    #[test]
+    fn test_verify_fails_without_passed_output() {
+        let verdict = verify_rom(printing_rom("01:01\nFailed\n"), 10).unwrap();
+        assert!(!verdict.passed);
+        assert_eq!(verdict.output, "01:01\nFailed\n");
+        assert!(!verify_rom(printing_rom(""), 10).unwrap().passed);
+    }
+}
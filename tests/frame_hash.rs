@@ -0,0 +1,46 @@
+use gumball::core::emulator::Emulator;
+use gumball::core::mmu::Mmu;
+use gumball::core::registers::{BGP, LCDC, OBP0};
+use sha2::{Digest, Sha256};
+
+// A cartridge that spins in place at 0x0100, with a tile pattern, a
+// background map and one sprite loaded straight into VRAM and OAM
+fn pattern_mmu() -> Mmu {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+    let mut mmu = Mmu::init();
+    mmu.initialize_from_bytes(&rom).unwrap();
+    for row in 0..8u16 {
+        mmu.set(0x8010 + row * 2, 0x55 << (row & 1));
+        mmu.set(0x8011 + row * 2, 0x0F);
+        mmu.set(0x8020 + row * 2, 0xFF >> row);
+        mmu.set(0x8021 + row * 2, 0xFF << row);
+    }
+    for tile in 0..0x400u16 {
+        mmu.set(0x9800 + tile, (tile % 3) as u8);
+    }
+    mmu.set(0xFE00, 16 + 40);
+    mmu.set(0xFE01, 8 + 60);
+    mmu.set(0xFE02, 2);
+    mmu.set(0xFE03, 0x20);
+    mmu.set(LCDC as u16, 0x93);
+    mmu.set(BGP as u16, 0xE4);
+    mmu.set(OBP0 as u16, 0xD2);
+    mmu
+}
+
+fn frame_hash(mmu: Mmu, frames: usize) -> String {
+    let mut emulator = Emulator::headless(mmu).unwrap();
+    emulator.run_frames(frames).unwrap();
+    format!("{:x}", Sha256::digest(emulator.framebuffer()))
+}
+
+#[test]
+fn test_pattern_frame_hash() {
+    // Update this only after checking the new output by eye
+    assert_eq!(
+        frame_hash(pattern_mmu(), 3),
+        "cadcf0f2dcfa7606185aecae06a7328442bc1cb8788eae44c3da8310e3981f49"
+    );
+    assert_eq!(frame_hash(pattern_mmu(), 3), frame_hash(pattern_mmu(), 3));
+}